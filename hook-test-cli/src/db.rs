@@ -0,0 +1,186 @@
+//! SQLite history of hook invocations (`--db`) and the canned `query` reports
+
+use clap::ValueEnum;
+use colored::*;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS invocations (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp   TEXT NOT NULL,
+    stage       TEXT NOT NULL,
+    input_hash  TEXT NOT NULL,
+    decision    TEXT,
+    exit_code   INTEGER,
+    latency_ms  INTEGER NOT NULL,
+    model       TEXT,
+    cost        REAL
+);
+CREATE INDEX IF NOT EXISTS idx_invocations_timestamp ON invocations (timestamp);
+";
+
+/// One row of the `invocations` table
+pub struct InvocationRecord {
+    pub stage: String,
+    pub input_hash: String,
+    /// continue/block/warn, or None when the hook failed or its output was unparseable
+    pub decision: Option<String>,
+    pub exit_code: Option<i32>,
+    pub latency_ms: u64,
+    pub model: Option<String>,
+    pub cost: Option<f64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum QueryKind {
+    /// Block rate per day
+    BlockRate,
+    /// Average and max latency per stage
+    LatencyByStage,
+    /// The 20 most recent invocations
+    Recent,
+}
+
+fn open(path: &Path) -> Result<Connection, String> {
+    let conn = Connection::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    conn.execute_batch(SCHEMA)
+        .map_err(|e| format!("Failed to create schema in {}: {}", path.display(), e))?;
+    Ok(conn)
+}
+
+pub fn record_invocation(path: &Path, record: &InvocationRecord) -> Result<(), String> {
+    let conn = open(path)?;
+    conn.execute(
+        "INSERT INTO invocations
+            (timestamp, stage, input_hash, decision, exit_code, latency_ms, model, cost)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            record.stage,
+            record.input_hash,
+            record.decision,
+            record.exit_code,
+            record.latency_ms as i64,
+            record.model,
+            record.cost,
+        ],
+    )
+    .map_err(|e| format!("Failed to record invocation: {}", e))?;
+    Ok(())
+}
+
+/// FNV-1a hash, stable across runs and platforms so identical inputs group together
+pub fn hash_input(input: &serde_json::Value) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in input.to_string().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub fn run_query(path: &Path, kind: QueryKind) -> Result<(), String> {
+    if !path.exists() {
+        return Err(format!("{} does not exist; record some runs with --db first", path.display()));
+    }
+    let conn = open(path)?;
+
+    match kind {
+        QueryKind::BlockRate => {
+            println!("{}", "=== Block Rate by Day ===".cyan().bold());
+            let mut stmt = conn
+                .prepare(
+                    "SELECT substr(timestamp, 1, 10) AS day,
+                            COUNT(*),
+                            SUM(decision = 'block'),
+                            SUM(decision IS NULL)
+                     FROM invocations GROUP BY day ORDER BY day",
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+
+            println!("  {:<12} {:>6} {:>8} {:>8} {:>8}", "Day", "Runs", "Blocked", "Errors", "Rate");
+            for row in rows {
+                let (day, runs, blocked, errors) = row.map_err(|e| e.to_string())?;
+                let rate = if runs > 0 { blocked as f64 * 100.0 / runs as f64 } else { 0.0 };
+                println!("  {:<12} {:>6} {:>8} {:>8} {:>7.1}%", day, runs, blocked, errors, rate);
+            }
+        }
+        QueryKind::LatencyByStage => {
+            println!("{}", "=== Latency by Stage ===".cyan().bold());
+            let mut stmt = conn
+                .prepare(
+                    "SELECT stage, COUNT(*), AVG(latency_ms), MAX(latency_ms)
+                     FROM invocations GROUP BY stage ORDER BY stage",
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, f64>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+
+            println!("  {:<10} {:>6} {:>12} {:>12}", "Stage", "Runs", "Avg (ms)", "Max (ms)");
+            for row in rows {
+                let (stage, runs, avg, max) = row.map_err(|e| e.to_string())?;
+                println!("  {:<10} {:>6} {:>12.1} {:>12}", stage, runs, avg, max);
+            }
+        }
+        QueryKind::Recent => {
+            println!("{}", "=== Recent Invocations ===".cyan().bold());
+            let mut stmt = conn
+                .prepare(
+                    "SELECT timestamp, stage, decision, latency_ms, model
+                     FROM invocations ORDER BY id DESC LIMIT 20",
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+
+            for row in rows {
+                let (timestamp, stage, decision, latency, model) = row.map_err(|e| e.to_string())?;
+                let decision = match decision.as_deref() {
+                    Some("block") => "block".red(),
+                    Some("warn") => "warn".yellow(),
+                    Some(other) => other.green(),
+                    None => "error".red(),
+                };
+                println!(
+                    "  {}  {:<6} {:<8} {:>6} ms  {}",
+                    timestamp,
+                    stage,
+                    decision,
+                    latency,
+                    model.unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
-use clap::{Parser, Subcommand};
+mod db;
+
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use colored::*;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
 use std::process::{Command, Stdio};
 use std::time::Instant;
 
@@ -12,332 +15,6339 @@ use std::time::Instant;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Replace the working directory and home directory in printed paths with <repo> and ~
+    #[arg(long, global = true)]
+    mask_paths: bool,
+    /// Print the exact wrapper JSON sent to the hook (to stderr) before spawning it
+    #[arg(long, global = true)]
+    show_wrapper: bool,
+    /// Print the hook's raw stdout (to stderr) before it is parsed
+    #[arg(long, global = true)]
+    show_response: bool,
+    /// Record every hook invocation into this SQLite database
+    #[arg(long, global = true, value_name = "PATH.sqlite")]
+    db: Option<std::path::PathBuf>,
+    /// Python interpreter used to run the hook (default: probe python3, then python)
+    #[arg(long, global = true, env = "HOOK_TEST_PYTHON")]
+    python: Option<String>,
+    /// Hook script passed to the interpreter
+    #[arg(long, global = true, env = "HOOK_TEST_SCRIPT", default_value = "review_orchestrator.py")]
+    script: std::path::PathBuf,
+    /// When to color output (auto honors NO_COLOR and disables color when not a terminal)
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    color: ColorChoice,
+    /// Same as --color never
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+    /// Print the hook command line, wrapper JSON and stderr of every call
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Print only decisions and results, without headers or progress
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Protocol version to send, for testing hooks written against an older contract
+    #[arg(long, global = true, default_value_t = PROTOCOL_VERSION)]
+    protocol_version: u32,
+    /// Override a config.json value for this run (repeatable); dotted keys reach
+    /// nested values, enabled_adapters takes a comma list, other values parse as JSON or a string
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_config_override)]
+    set: Vec<(String, serde_json::Value)>,
+    /// Directory holding config.json and plugin.json (default: the current directory)
+    #[arg(long, global = true, env = "HOOK_TEST_CONFIG_DIR", value_parser = parse_existing_dir)]
+    config_dir: Option<String>,
+    /// RNG seed for fuzz snippets and generate --count variants; the same seed
+    /// produces byte-identical output (default: a fresh seed from the system RNG)
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Run a benchmark test on the hook system
-    Bench {
-        /// Number of iterations
-        #[arg(short, long, default_value = "10")]
+    Bench(BenchArgs),
+    /// Simulate a tool call to trigger hooks
+    Simulate(SimulateArgs),
+    /// Actually invoke the Python hook with test data
+    Invoke(InvokeArgs),
+    /// Show hook system status
+    Status {
+        /// Print a JSON object instead of the human-readable report
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the adapters the orchestrator supports and whether config.json enables them
+    ListAdapters,
+    /// Write a starter config.json and plugin.json into the current directory
+    Init {
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+    /// Generate a test file with intentional issues
+    Generate(GenerateArgs),
+    /// Feed the tool calls from a recorded transcript through the hook
+    Replay {
+        /// JSON file holding an array of tool-call events
+        transcript: std::path::PathBuf,
+        /// Stage (plan, code, test, final)
+        #[arg(short, long, default_value = "code")]
+        stage: String,
+    },
+    /// Run every file in a directory through the hook and score the decisions
+    Score {
+        /// Directory of files to review (e.g. from `generate --count`)
+        dir: std::path::PathBuf,
+        /// Stage (plan, code, test, final)
+        #[arg(short, long, default_value = "code")]
+        stage: String,
+        /// Decision the hook should reach for every file
+        #[arg(short, long, value_enum)]
+        expect: Expectation,
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+    },
+    /// Send randomly generated flawed snippets through the hook and report the catch rate
+    Fuzz {
+        /// Number of snippets to generate
+        #[arg(short, long, default_value = "20")]
         iterations: u32,
-        /// Actually invoke the Python hook (requires Python)
+        /// Stage (plan, code, test, final)
+        #[arg(short, long, default_value = "code")]
+        stage: String,
+    },
+    /// Time one hook call phase by phase: spawn, stdin write, wait, output read and parse
+    Profile {
+        /// Stage (plan, code, test, final)
+        #[arg(short, long, default_value = "code")]
+        stage: String,
+    },
+    /// Run one file through every stage independently and show where it is blocked
+    CompareStages {
+        /// File whose contents are sent as the edit
+        file: std::path::PathBuf,
+    },
+    /// Run the cases listed in <DIR>/suite.json and check each decision
+    TestSuite {
+        /// Directory holding suite.json and the files it names
+        dir: std::path::PathBuf,
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+    },
+    /// Compare two Invoke --save files and report changed decisions
+    Diff {
+        /// Results from before the change
+        baseline: std::path::PathBuf,
+        /// Results from after the change
+        current: std::path::PathBuf,
+    },
+    /// Run an ordered list of tool calls as one session
+    Scenario {
+        /// JSON array of steps ({"stage": ..., "tool": ..., "file": ..., extra input fields})
+        file: std::path::PathBuf,
+        /// Actually invoke the Python hook for each step
         #[arg(long)]
         real: bool,
+        /// Tool input templates, as for `simulate --templates`
+        #[arg(long, value_name = "FILE")]
+        templates: Option<std::path::PathBuf>,
+        #[command(flatten)]
+        overrides: InputOverrides,
     },
-    /// Simulate a tool call to trigger hooks
-    Simulate {
-        /// Tool name (Edit, Write, Bash, TodoWrite)
-        #[arg(short, long)]
-        tool: String,
-        /// File path for Edit/Write tools
-        #[arg(short, long)]
-        file: Option<String>,
-    },
-    /// Actually invoke the Python hook with test data
-    Invoke {
+    /// Re-invoke the hook every time a file is saved
+    Watch {
+        /// File to watch, or a directory to watch recursively; a changed file's contents are sent as the edit
+        path: std::path::PathBuf,
         /// Stage (plan, code, test, final)
         #[arg(short, long, default_value = "code")]
         stage: String,
-        /// Code content to review
-        #[arg(short, long)]
-        code: Option<String>,
-        /// File path
-        #[arg(short, long)]
-        file: Option<String>,
+        /// How long saves must stop before the hook runs, in milliseconds
+        #[arg(long, value_name = "MS", default_value = "300")]
+        watch_debounce: u64,
+        /// Don't review files matching this glob (repeatable); added to the globs in .hookignore
+        #[arg(long, value_name = "GLOB")]
+        watch_ignore: Vec<String>,
     },
-    /// Show hook system status
-    Status,
-    /// Generate a test file with intentional issues
-    Generate {
-        /// Type of issue (bug, security, style)
-        #[arg(short, long, default_value = "bug")]
-        issue_type: String,
+    /// Run a canned report over the invocations recorded with --db
+    Query {
+        #[arg(value_enum)]
+        query: db::QueryKind,
+    },
+    /// Print a shell completion script, e.g. `hook-test completions zsh > _hook-test`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
     },
 }
 
+#[derive(Args)]
+struct SimulateArgs {
+    /// Tool name (Edit, MultiEdit, Write, Read, Grep, Glob, Bash, TodoWrite)
+    #[arg(short, long, required_unless_present = "batch")]
+    tool: Option<String>,
+    /// File path for Edit/Write tools
+    #[arg(short, long)]
+    file: Option<String>,
+    /// Replay tool calls from a JSONL file ({"tool": ..., "file": ..., extra input fields})
+    #[arg(long, conflicts_with_all = ["tool", "file"])]
+    batch: Option<std::path::PathBuf>,
+    /// Actually invoke the Python hook instead of printing the expected output
+    #[arg(long)]
+    real: bool,
+    /// Stage (plan, code, test, final) to simulate or, with --real, to invoke
+    #[arg(short, long, default_value = "code")]
+    stage: String,
+    /// systemMessage of the simulated output; {stage} is replaced with --stage
+    #[arg(long, value_name = "TEMPLATE", default_value = SIMULATED_MESSAGE)]
+    message: String,
+    /// Replay the --batch file this many times
+    #[arg(long, default_value = "1", requires = "batch", value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+    /// Stop at the first malformed --batch entry and exit non-zero instead of skipping it
+    #[arg(long, requires = "batch")]
+    abort_on_parse_error: bool,
+    /// JSON array of {"content", "status"} objects to send as TodoWrite's todos
+    #[arg(long, conflicts_with = "batch")]
+    todos_file: Option<std::path::PathBuf>,
+    /// JSON object of tool name to tool_input template ({{file}}, {{cwd}} are filled in);
+    /// overrides the built-in inputs and adds tools the simulator doesn't know
+    #[arg(long, value_name = "FILE")]
+    templates: Option<std::path::PathBuf>,
+    #[command(flatten)]
+    overrides: InputOverrides,
+}
+
+/// The message the orchestrator sends when a review passes
+const SIMULATED_MESSAGE: &str = "[자기검열-{stage}] ✅ 검토 통과";
+
+/// Golden-file checking for Score and TestSuite
+#[derive(Args)]
+struct SnapshotArgs {
+    /// Compare each file's decision and message hash against this snapshot and fail on drift
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<std::path::PathBuf>,
+    /// Write the current results to --snapshot instead of comparing
+    #[arg(long, requires = "snapshot")]
+    update_snapshots: bool,
+}
+
+/// Fixed session_id/cwd for reproducible hook inputs
+#[derive(Args)]
+struct InputOverrides {
+    /// Session ID to send instead of a timestamp-based one
+    #[arg(long)]
+    session_id: Option<String>,
+    /// Working directory to report to the hook instead of the current one
+    #[arg(long, value_parser = parse_existing_dir)]
+    cwd: Option<String>,
+}
+
+impl InputOverrides {
+    fn apply(&self, input: &mut serde_json::Value) {
+        if let Some(session_id) = &self.session_id {
+            input["session_id"] = serde_json::json!(session_id);
+        }
+        if let Some(cwd) = &self.cwd {
+            input["cwd"] = serde_json::json!(cwd);
+        }
+    }
+}
+
+/// One step of a `scenario` file
+#[derive(Deserialize)]
+struct ScenarioStep {
+    #[serde(default = "default_suite_stage")]
+    stage: String,
+    #[serde(flatten)]
+    call: BatchEntry,
+}
+
+/// One line of a `simulate --batch` file
+#[derive(Deserialize)]
+struct BatchEntry {
+    tool: String,
+    #[serde(default)]
+    file: Option<String>,
+    /// Any other fields are merged into the generated tool_input
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Number of iterations
+    #[arg(short, long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+    /// Actually invoke the Python hook (requires Python)
+    #[arg(long)]
+    real: bool,
+    /// Comma-separated payload sizes to benchmark separately (e.g. 1k,10k,50k)
+    #[arg(long, value_delimiter = ',', value_parser = parse_byte_size)]
+    size_sweep: Vec<usize>,
+    /// Output format for the results
+    #[arg(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+    /// Throwaway iterations run first and excluded from the stats (e.g. Python cold start)
+    #[arg(long, default_value = "0")]
+    warmup: u32,
+    /// Worker threads to spread the iterations across
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: u32,
+    /// Write per-iteration timings to this CSV file
+    #[arg(long)]
+    csv: Option<std::path::PathBuf>,
+    /// Retry a failed start or non-zero exit this many times with exponential backoff
+    #[arg(long, default_value = "0")]
+    retries: u32,
+    /// Extra environment variable for the hook process (repeatable)
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+    /// Append this run's summary to a JSONL history file
+    #[arg(long, value_name = "PATH")]
+    history: Option<std::path::PathBuf>,
+    /// Fail if the average is slower than the latest entry in this history file
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<std::path::PathBuf>,
+    /// Print an ASCII histogram of the latencies (text output only)
+    #[arg(long)]
+    histogram: bool,
+    /// Percent slowdown against --baseline that counts as a regression
+    #[arg(long, default_value = "20", requires = "baseline")]
+    regression_threshold: f64,
+    /// Kill a --real iteration if it runs longer than this many seconds
+    #[arg(long, default_value = "30")]
+    timeout: u64,
+    /// Keep one `<script> --serve` process per thread and time only the requests
+    #[arg(long, requires = "real", conflicts_with = "size_sweep")]
+    persistent: bool,
+    /// Print the command and stdin payload of one iteration instead of running
+    #[arg(long, requires = "real")]
+    dry_run: bool,
+    /// Write the summary as Prometheus text-format metrics (for a textfile collector)
+    #[arg(long, value_name = "PATH")]
+    prometheus: Option<std::path::PathBuf>,
+}
+
+/// One measured benchmark iteration
+struct BenchSample {
+    iteration: u32,
+    duration: std::time::Duration,
+    success: bool,
+    timed_out: bool,
+    /// The hook answered, but not with a parseable HookOutput
+    invalid_output: bool,
+    /// How the hook process exited; None when no process finished for this iteration
+    status: Option<std::process::ExitStatus>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+struct InvokeArgs {
+    /// Stage (plan, code, test, final)
+    #[arg(short, long, default_value = "code")]
+    stage: String,
+    /// Code content to review
+    #[arg(short, long)]
+    code: Option<String>,
+    /// File path; repeat to review several files in parallel
+    #[arg(short, long)]
+    file: Vec<String>,
+    /// Read more file paths to review, one per line
+    #[arg(long, value_name = "LIST", conflicts_with_all = ["stdin", "editor", "old_file"])]
+    files_from: Option<std::path::PathBuf>,
+    /// Hook calls to run at once when reviewing several files
+    #[arg(long, default_value = "4", value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: u32,
+    /// Reject hook output containing fields unknown to HookOutput
+    #[arg(long)]
+    strict_json: bool,
+    /// Emit a JSON result object to this stream instead of the human output
+    /// (with stderr, stdout carries only the raw hook output)
+    #[arg(long, value_enum)]
+    json_stream: Option<JsonStream>,
+    /// Run the same input N times and report decision stability (0 runs until Ctrl-C)
+    #[arg(long, default_value = "1")]
+    repeat: u32,
+    /// Milliseconds to wait between --repeat runs (ignored for a single run)
+    #[arg(long, default_value = "0")]
+    interval: u64,
+    /// Compose the code to review in $VISUAL / $EDITOR (like git commit)
+    #[arg(long)]
+    editor: bool,
+    /// Kill the hook if it runs longer than this many seconds
+    #[arg(long, default_value = "30")]
+    timeout: u64,
+    /// Retry a failed start or non-zero exit this many times with exponential backoff
+    #[arg(long, default_value = "0")]
+    retries: u32,
+    /// Extra environment variable for the hook process (repeatable)
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+    /// Read a complete HookInput JSON from stdin (ignores --code/--file)
+    #[arg(long, conflicts_with = "editor")]
+    stdin: bool,
+    /// Read the code to review (new_string) from stdin
+    #[arg(long, conflicts_with_all = ["code", "file", "files_from", "stdin", "editor", "old_file"])]
+    code_stdin: bool,
+    /// Run plan → code → test → final in sequence, stopping at the first block
+    #[arg(long, conflicts_with_all = ["stage", "repeat", "json_stream"])]
+    all_stages: bool,
+    /// With --all-stages, print one JSON line per stage as it finishes, then a summary line
+    #[arg(long, requires = "all_stages", conflicts_with = "dry_run")]
+    jsonl: bool,
+    /// Original file contents, sent as old_string (requires --new-file)
+    #[arg(long, requires = "new_file", conflicts_with_all = ["code", "file", "stdin", "editor"])]
+    old_file: Option<String>,
+    /// Edited file contents, sent as new_string (requires --old-file)
+    #[arg(long, requires = "old_file")]
+    new_file: Option<String>,
+    /// Print the hook's stdout line by line as it arrives
+    #[arg(long, conflicts_with = "json_stream")]
+    stream: bool,
+    /// Only run this adapter (repeatable); defaults to config.json's enabled_adapters
+    #[arg(long = "adapter", value_name = "NAME")]
+    adapters: Vec<String>,
+    /// Language to report in tool_input; inferred from the file extension by default
+    #[arg(long)]
+    language: Option<String>,
+    /// Remove comments from the code before sending it, using that language's comment syntax
+    #[arg(long)]
+    strip_comments: bool,
+    #[command(flatten)]
+    overrides: InputOverrides,
+    /// Append each stage's result to this JSONL file for a later `diff`
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["repeat", "json_stream"])]
+    save: Option<std::path::PathBuf>,
+    /// Append a full record (input, raw output, decision, timing) per stage to this JSONL file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["repeat", "json_stream"])]
+    output_file: Option<std::path::PathBuf>,
+    /// Fail unless the hook makes this decision (exit 0 on a match, even for block)
+    #[arg(long, value_enum, conflicts_with_all = ["all_stages", "repeat", "json_stream"])]
+    expect: Option<Expectation>,
+    /// Print the command and stdin payload instead of running the hook
+    #[arg(long)]
+    dry_run: bool,
+    /// On a block, ask the hook for a detailed rationale and print it
+    #[arg(long, conflicts_with_all = ["all_stages", "repeat", "json_stream"])]
+    explain: bool,
+    /// Print at most this many lines of the system message (0 for all);
+    /// --output-file still records everything
+    #[arg(long, value_name = "N")]
+    max_lines: Option<usize>,
+}
+
+/// How invoke_once checks and prints a single response
+#[derive(Clone, Copy, Default)]
+struct ResponseOptions {
+    strict_json: bool,
+    /// --explain: ask the hook for a rationale on a block
+    explain: bool,
+    /// --max-lines: cap on printed system message lines; None or 0 prints all
+    max_lines: Option<usize>,
+}
+
+/// Files Invoke appends each stage's outcome to
+#[derive(Clone, Copy, Default)]
+struct ResultSinks<'a> {
+    /// --save: compact records for `diff`
+    save: Option<&'a std::path::Path>,
+    /// --output-file: audit records with the input and raw output
+    output_file: Option<&'a std::path::Path>,
+}
+
+/// One line of an Invoke --save file; Diff matches lines by session_id and stage
+#[derive(Serialize, Deserialize)]
+struct SavedResult {
+    session_id: String,
+    stage: String,
+    /// continue/block/warn, or None when the hook failed
+    decision: Option<String>,
+    #[serde(rename = "systemMessage")]
+    system_message: Option<String>,
+    error: Option<String>,
+}
+
+/// Exit status when the hook blocked the change
+const EXIT_BLOCKED: i32 = 1;
+/// Exit status when the hook could not be run or its output could not be understood
+const EXIT_HOOK_ERROR: i32 = 2;
+/// Exit status when the hook was killed for running past --timeout, as with timeout(1)
+const EXIT_TIMEOUT: i32 = 124;
+/// Exit status when Invoke --expect didn't match the hook's decision
+const EXIT_ASSERTION_FAILED: i32 = 3;
+
+/// Stages in the order the orchestrator runs them during a session, unless
+/// config.json lists its own `review_stages`
+const STAGES: [&str; 4] = ["plan", "code", "test", "final"];
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum JsonStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    /// Type of issue (bug, security, style, performance, concurrency)
+    #[arg(short, long, default_value = "bug")]
+    issue_type: String,
+    /// Language of the generated snippet
+    #[arg(short, long, value_enum, default_value = "rust")]
+    lang: Lang,
+    /// Number of variant files to write
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+    /// Directory to write the files into (created if missing)
+    #[arg(short, long, default_value = ".")]
+    out_dir: std::path::PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Expectation {
+    Continue,
+    Block,
+}
+
+impl Expectation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Expectation::Continue => "continue",
+            Expectation::Block => "block",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Lang {
+    Rust,
+    Python,
+    Javascript,
+}
+
+impl Lang {
+    fn extension(self) -> &'static str {
+        match self {
+            Lang::Rust => "rs",
+            Lang::Python => "py",
+            Lang::Javascript => "js",
+        }
+    }
+}
+
+/// One tool call in a recorded transcript; accepts both hook input
+/// (`tool_name`/`tool_input`) and API tool_use (`name`/`input`) field names
+#[derive(Deserialize)]
+struct TranscriptEvent {
+    #[serde(alias = "name")]
+    tool_name: String,
+    #[serde(default, alias = "input")]
+    tool_input: serde_json::Value,
+    session_id: Option<String>,
+    cwd: Option<String>,
+}
+
+/// One entry of suite.json, keyed by file name relative to the suite directory
+#[derive(Deserialize)]
+struct SuiteCase {
+    expect: Expectation,
+    #[serde(default = "default_suite_stage")]
+    stage: String,
+}
+
+fn default_suite_stage() -> String {
+    "code".to_string()
+}
+
+/// Tools the simulator knows how to build input for; Replay skips the rest
+const SUPPORTED_TOOLS: [&str; 8] = ["Edit", "MultiEdit", "Write", "Read", "Grep", "Glob", "Bash", "TodoWrite"];
+
 #[derive(Serialize, Deserialize, Debug)]
 struct HookInput {
     session_id: String,
     tool_name: String,
     tool_input: serde_json::Value,
     cwd: String,
+    /// Left unset by the builders; hook_wrapper fills in --protocol-version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
 }
 
+/// Version of the CLI ↔ hook JSON contract sent in every wrapper
+const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct HookOutput {
-    #[serde(rename = "continue")]
-    should_continue: bool,
-    #[serde(rename = "systemMessage")]
+    #[serde(rename = "continue", default, skip_serializing_if = "Option::is_none")]
+    should_continue: Option<bool>,
+    #[serde(rename = "systemMessage", default, deserialize_with = "deserialize_system_message")]
     system_message: Option<String>,
+    /// Orchestrator-specific decision word, normalized through `decision_map`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    decision: Option<String>,
+    /// Protocol version the hook speaks, if it reports one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// Structured findings, for hooks that report more than a free-text message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issues: Option<Vec<Issue>>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// One finding reported by the hook
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Issue {
+    /// "error", "warning" or "info"; anything else sorts after info
+    severity: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+}
 
-    match cli.command {
-        Commands::Bench { iterations, real } => run_benchmark(iterations, real),
-        Commands::Simulate { tool, file } => run_simulation(&tool, file),
-        Commands::Invoke { stage, code, file } => invoke_hook(&stage, code, file),
-        Commands::Status => show_status(),
-        Commands::Generate { issue_type } => generate_test_file(&issue_type),
+impl Issue {
+    fn severity_rank(&self) -> u8 {
+        match self.severity.trim().to_lowercase().as_str() {
+            "error" => 0,
+            "warning" | "warn" => 1,
+            "info" => 2,
+            _ => 3,
+        }
     }
 }
 
-fn run_benchmark(iterations: u32, real: bool) {
-    println!("{}", "=== Hook System Benchmark ===".cyan().bold());
-    println!("Running {} iterations (real: {})...\n", iterations, real);
+/// The CLI's internal view of a hook decision
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Decision {
+    Continue,
+    Block,
+    Warn,
+}
 
-    let start = Instant::now();
-    let mut timings: Vec<std::time::Duration> = Vec::new();
+impl Decision {
+    fn parse(word: &str) -> Option<Decision> {
+        match word.trim().to_lowercase().as_str() {
+            "continue" => Some(Decision::Continue),
+            "block" => Some(Decision::Block),
+            "warn" => Some(Decision::Warn),
+            _ => None,
+        }
+    }
 
-    for i in 1..=iterations {
-        let iter_start = Instant::now();
-
-        if real {
-            // Actually call the Python hook
-            let result = call_python_hook("code", &serde_json::json!({
-                "session_id": format!("bench-{}", i),
-                "tool_name": "Edit",
-                "tool_input": {
-                    "file_path": "bench_test.rs",
-                    "old_string": "fn old() {}",
-                    "new_string": "fn new() {}"
-                },
-                "cwd": std::env::current_dir().unwrap().to_string_lossy().to_string()
-            }));
-
-            match result {
-                Ok(_) => print!("{}", ".".green()),
-                Err(_) => print!("{}", "x".red()),
-            }
-        } else {
-            // Simulate hook call delay
-            std::thread::sleep(std::time::Duration::from_millis(50));
-            print!("{}", ".".green());
+    fn as_str(self) -> &'static str {
+        match self {
+            Decision::Continue => "continue",
+            Decision::Block => "block",
+            Decision::Warn => "warn",
         }
+    }
 
-        let elapsed = iter_start.elapsed();
-        timings.push(elapsed);
+    fn label(self) -> ColoredString {
+        match self {
+            Decision::Continue => "✅ CONTINUE".green(),
+            Decision::Block => "❌ BLOCKED".red(),
+            Decision::Warn => "⚠️  WARN".yellow(),
+        }
     }
-    println!();
 
-    let total = start.elapsed();
-    let avg = total / iterations;
+    fn allows_continue(self) -> bool {
+        self != Decision::Block
+    }
+}
 
-    // Calculate stats
-    let min = timings.iter().min().unwrap();
-    let max = timings.iter().max().unwrap();
+impl HookOutput {
+    /// Resolves the effective decision, preferring `decision` over `continue`.
+    /// Returns a warning when the decision word is not in the map.
+    fn resolve_decision(&self, decision_map: &HashMap<String, Decision>) -> (Decision, Option<String>) {
+        if let Some(word) = &self.decision {
+            let key = word.trim().to_lowercase();
+            if let Some(mapped) = decision_map.get(&key).copied().or_else(|| Decision::parse(&key)) {
+                return (mapped, None);
+            }
+            return (
+                Decision::Block,
+                Some(format!("Unmapped decision '{}'; treating as block (add it to decision_map in config.json)", word)),
+            );
+        }
 
-    println!("\n{}", "Results:".green().bold());
-    println!("  Total time: {:?}", total);
-    println!("  Average: {:?}", avg);
-    println!("  Min: {:?}", min);
-    println!("  Max: {:?}", max);
-    if avg.as_millis() > 0 {
-        println!("  Throughput: {:.2} calls/sec", 1000.0 / avg.as_millis() as f64);
+        match self.should_continue {
+            Some(false) => (Decision::Block, None),
+            _ => (Decision::Continue, None),
+        }
     }
 }
 
-fn run_simulation(tool: &str, file: Option<String>) {
-    println!("{}", format!("=== Simulating {} Tool ===", tool).cyan().bold());
+/// The parts of config.json the CLI understands; other keys belong to the orchestrator
+#[derive(Deserialize, Debug)]
+struct Config {
+    enabled_adapters: Vec<String>,
+    #[serde(default)]
+    timeout_seconds: Option<u64>,
+    #[serde(default)]
+    parallel_execution: Option<bool>,
+    #[serde(default)]
+    decision_map: Option<HashMap<String, String>>,
+}
 
-    let hook_input = HookInput {
-        session_id: format!("test-{}", chrono::Utc::now().timestamp()),
-        tool_name: tool.to_string(),
-        tool_input: match tool {
-            "Edit" => serde_json::json!({
-                "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
-                "old_string": "fn old() {}",
-                "new_string": "fn new() { /* TODO: implement */ }"
-            }),
-            "Write" => serde_json::json!({
-                "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
-                "content": "fn main() {\n    println!(\"test\");\n}"
-            }),
-            "TodoWrite" => serde_json::json!({
-                "todos": [
-                    {"content": "Implement feature", "status": "pending"},
-                    {"content": "Write tests", "status": "pending"}
-                ]
-            }),
-            _ => serde_json::json!({}),
-        },
-        cwd: std::env::current_dir()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| ".".to_string()),
+/// Loads config.json with the --set overrides applied and validates the result,
+/// describing exactly what is wrong when it can't
+fn load_config(path: &std::path::Path, overrides: &[(String, serde_json::Value)]) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("unreadable ({})", e))?;
+    let mut value: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("invalid JSON at line {} column {}", e.line(), e.column()))?;
+    if value.is_object() {
+        apply_config_overrides(&mut value, overrides);
+    }
+
+    let Some(obj) = value.as_object() else {
+        return Err("top level must be an object".to_string());
     };
+    match obj.get("enabled_adapters") {
+        None => return Err("missing 'enabled_adapters'".to_string()),
+        Some(adapters) if !adapters.as_array().is_some_and(|a| a.iter().all(|v| v.is_string())) => {
+            return Err("'enabled_adapters' must be an array of strings".to_string());
+        }
+        Some(_) => {}
+    }
 
-    println!("\n{}", "Hook Input:".yellow());
-    println!("{}", serde_json::to_string_pretty(&hook_input).unwrap());
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
 
-    // Simulate hook response
-    let response = HookOutput {
-        should_continue: true,
-        system_message: Some("[자기검열-code] ✅ 검토 통과".to_string()),
-    };
+/// config.json as the hook will see it: the file (or `{}` when missing or
+/// unparseable) with the --set overrides applied
+fn effective_config(config_dir: &std::path::Path, overrides: &[(String, serde_json::Value)]) -> serde_json::Value {
+    let mut config = std::fs::read_to_string(config_dir.join("config.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .filter(|value| value.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    apply_config_overrides(&mut config, overrides);
+    config
+}
 
-    println!("\n{}", "Expected Hook Output:".yellow());
-    println!("{}", serde_json::to_string_pretty(&response).unwrap());
+/// Sets each dotted key, creating (or replacing non-object) parents as needed
+fn apply_config_overrides(config: &mut serde_json::Value, overrides: &[(String, serde_json::Value)]) {
+    for (key, value) in overrides {
+        let mut target = &mut *config;
+        let mut parts = key.split('.').peekable();
+        while let Some(part) = parts.next() {
+            if !target.is_object() {
+                *target = serde_json::json!({});
+            }
+            let obj = target.as_object_mut().expect("just made an object");
+            if parts.peek().is_none() {
+                obj.insert(part.to_string(), value.clone());
+                break;
+            }
+            target = obj.entry(part).or_insert_with(|| serde_json::json!({}));
+        }
+    }
 }
 
-fn show_status() {
-    println!("{}", "=== Hook System Status ===".cyan().bold());
+fn parse_config_override(raw: &str) -> Result<(String, serde_json::Value), String> {
+    let (key, value) = raw.split_once('=').ok_or_else(|| format!("'{}' is not KEY=VALUE", raw))?;
+    let key = key.trim();
+    if key.is_empty() || key.split('.').any(str::is_empty) {
+        return Err(format!("invalid config key '{}'", key));
+    }
+    let value = if key == "enabled_adapters" {
+        serde_json::json!(value.split(',').map(str::trim).filter(|a| !a.is_empty()).collect::<Vec<_>>())
+    } else {
+        serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()))
+    };
+    Ok((key.to_string(), value))
+}
 
-    // Check if config exists
-    let config_path = std::path::Path::new("config.json");
-    let plugin_path = std::path::Path::new("plugin.json");
+/// Reads `decision_map` from config.json, e.g. `{"approve": "continue", "reject": "block"}`
+fn load_decision_map(
+    config_dir: &std::path::Path,
+    overrides: &[(String, serde_json::Value)],
+) -> HashMap<String, Decision> {
+    let mut map = HashMap::new();
+    let config = effective_config(config_dir, overrides);
 
-    println!("\n{}", "Configuration Files:".yellow());
-    println!("  config.json: {}", if config_path.exists() { "✅ Found".green() } else { "❌ Missing".red() });
-    println!("  plugin.json: {}", if plugin_path.exists() { "✅ Found".green() } else { "❌ Missing".red() });
-
-    if config_path.exists() {
-        if let Ok(content) = std::fs::read_to_string(config_path) {
-            if let Ok(config) = serde_json::from_str::<serde_json::Value>(&content) {
-                println!("\n{}", "Enabled Adapters:".yellow());
-                if let Some(adapters) = config.get("enabled_adapters").and_then(|a| a.as_array()) {
-                    for adapter in adapters {
-                        println!("  - {}", adapter.as_str().unwrap_or("unknown").green());
-                    }
+    if let Some(entries) = config.get("decision_map").and_then(|m| m.as_object()) {
+        for (word, target) in entries {
+            match target.as_str().and_then(Decision::parse) {
+                Some(decision) => {
+                    map.insert(word.trim().to_lowercase(), decision);
                 }
+                None => eprintln!(
+                    "{} decision_map entry '{}' must map to continue, block or warn",
+                    "Warning:".yellow(),
+                    word
+                ),
             }
         }
     }
+
+    map
 }
 
-fn generate_test_file(issue_type: &str) {
-    println!("{}", format!("=== Generating Test File ({}) ===", issue_type).cyan().bold());
+/// Reads `review_stages` from config.json, falling back to STAGES when it is absent or invalid
+fn load_review_stages(config_dir: &std::path::Path, overrides: &[(String, serde_json::Value)]) -> Vec<String> {
+    let default = || STAGES.iter().map(|s| s.to_string()).collect();
+    let config = effective_config(config_dir, overrides);
+    let Some(value) = config.get("review_stages") else {
+        return default();
+    };
 
-    let content = match issue_type {
-        "bug" => r#"// Test file with intentional bug
-fn calculate_average(numbers: &[i32]) -> i32 {
-    let sum: i32 = numbers.iter().sum();
-    sum / numbers.len() as i32  // BUG: Division by zero if empty
+    let stages: Option<Vec<String>> = value.as_array().and_then(|stages| {
+        stages.iter().map(|s| s.as_str().filter(|s| !s.trim().is_empty()).map(String::from)).collect()
+    });
+    match stages {
+        Some(stages) if !stages.is_empty() => stages,
+        _ => {
+            eprintln!(
+                "{} review_stages must be a non-empty array of stage names; using {}",
+                "Warning:".yellow(),
+                STAGES.join(", ")
+            );
+            default()
+        }
+    }
 }
 
-fn main() {
-    let empty: Vec<i32> = vec![];
-    println!("Average: {}", calculate_average(&empty));
+impl Commands {
+    /// The --stage a command was given, when it takes one and uses it
+    fn stage(&self) -> Option<&str> {
+        match self {
+            Commands::Invoke(args) if !args.all_stages => Some(&args.stage),
+            Commands::Simulate(args) => Some(&args.stage),
+            Commands::Replay { stage, .. }
+            | Commands::Score { stage, .. }
+            | Commands::Fuzz { stage, .. }
+            | Commands::Profile { stage }
+            | Commands::Watch { stage, .. } => Some(stage),
+            _ => None,
+        }
+    }
 }
-"#,
-        "security" => r#"// Test file with security issue
-use std::process::Command;
 
-fn run_user_command(input: &str) {
-    // SECURITY: Command injection vulnerability
-    Command::new("sh")
-        .arg("-c")
-        .arg(input)  // Unsanitized user input!
-        .spawn()
-        .expect("Failed to execute");
-}
+/// Accepts `systemMessage` as a single string or an array of strings joined with newlines
+fn deserialize_system_message<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SystemMessage {
+        Text(String),
+        Lines(Vec<String>),
+    }
 
-fn main() {
-    run_user_command("echo hello; rm -rf /");
+    Ok(Option::<SystemMessage>::deserialize(deserializer)?.map(|message| match message {
+        SystemMessage::Text(text) => text,
+        SystemMessage::Lines(lines) => lines.join("\n"),
+    }))
 }
-"#,
-        "style" => r#"// Test file with style issues
-fn BadFunctionName() {  // Should be snake_case
-    let X = 5;  // Should be lowercase
-    let unused_var = 10;  // Unused variable
-    println!("{}",X);  // Missing space
+
+/// Field names HookOutput understands, used to report unexpected keys
+const HOOK_OUTPUT_FIELDS: &[&str] = &["continue", "systemMessage", "decision", "version", "issues"];
+
+/// Strict variant of HookOutput for --strict-json protocol checks
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StrictHookOutput {
+    #[serde(rename = "continue", default)]
+    should_continue: Option<bool>,
+    #[serde(rename = "systemMessage", default, deserialize_with = "deserialize_system_message")]
+    system_message: Option<String>,
+    #[serde(default)]
+    decision: Option<String>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    issues: Option<Vec<Issue>>,
 }
-"#,
-        _ => "// Unknown issue type\nfn main() {}\n",
-    };
 
-    let filename = format!("test_{}.rs", issue_type);
-    std::fs::write(&filename, content).expect("Failed to write file");
+impl From<StrictHookOutput> for HookOutput {
+    fn from(strict: StrictHookOutput) -> Self {
+        HookOutput {
+            should_continue: strict.should_continue,
+            system_message: strict.system_message,
+            decision: strict.decision,
+            version: strict.version,
+            issues: strict.issues,
+        }
+    }
+}
 
-    println!("\n{} {}", "Created:".green(), filename);
-    println!("\n{}", "File Content:".yellow());
-    println!("{}", content);
-    println!("\n{}", "Now use 'hook-test simulate -t Edit -f <file>' to test the hook".cyan());
+/// Masks local directory structure in printed output; the hook still receives real paths
+struct PathMasker {
+    cwd: Option<String>,
+    home: Option<String>,
 }
 
-fn invoke_hook(stage: &str, code: Option<String>, file: Option<String>) {
-    println!("{}", format!("=== Invoking Hook (stage: {}) ===", stage).cyan().bold());
+impl PathMasker {
+    fn new(enabled: bool) -> Self {
+        if !enabled {
+            return PathMasker { cwd: None, home: None };
+        }
 
-    let code_content = if let Some(c) = code {
-        c
-    } else if let Some(f) = &file {
-        std::fs::read_to_string(f).unwrap_or_else(|_| "// Could not read file".to_string())
-    } else {
-        "fn example() { /* test code */ }".to_string()
-    };
+        let cwd = std::env::current_dir()
+            .ok()
+            .map(|p| p.to_string_lossy().to_string());
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .ok()
+            .filter(|h| !h.is_empty());
 
-    let hook_input = serde_json::json!({
-        "session_id": format!("invoke-{}", chrono::Utc::now().timestamp()),
-        "tool_name": "Edit",
-        "tool_input": {
-            "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
-            "old_string": "",
-            "new_string": code_content
-        },
-        "cwd": std::env::current_dir().unwrap().to_string_lossy().to_string()
-    });
+        PathMasker { cwd, home }
+    }
 
-    println!("\n{}", "Sending to Python hook...".yellow());
-    let start = Instant::now();
+    fn mask(&self, text: &str) -> String {
+        let mut masked = text.to_string();
+        // cwd usually lives under home, so replace the longer prefix first
+        if let Some(cwd) = &self.cwd {
+            masked = masked.replace(cwd.as_str(), "<repo>");
+        }
+        if let Some(home) = &self.home {
+            masked = masked.replace(home.as_str(), "~");
+        }
+        masked
+    }
 
-    match call_python_hook(stage, &hook_input) {
-        Ok(output) => {
-            let elapsed = start.elapsed();
-            println!("\n{} ({:?})", "Hook Response:".green().bold(), elapsed);
+    fn mask_json(&self, value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::String(s) => serde_json::Value::String(self.mask(s)),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(|v| self.mask_json(v)).collect())
+            }
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.iter().map(|(k, v)| (k.clone(), self.mask_json(v))).collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
 
-            if let Ok(parsed) = serde_json::from_str::<HookOutput>(&output) {
-                let status = if parsed.should_continue {
-                    "✅ CONTINUE".green()
-                } else {
-                    "❌ BLOCKED".red()
-                };
-                println!("  Decision: {}", status);
+/// Settings shared by every command that spawns the Python hook
+#[derive(Clone)]
+struct HookOptions {
+    show_wrapper: bool,
+    show_response: bool,
+    decision_map: HashMap<String, Decision>,
+    db_path: Option<std::path::PathBuf>,
+    /// Interpreter, resolved the first time a command needs it
+    python: LazyPython,
+    script: std::path::PathBuf,
+    /// Kill the hook process after this long; None waits indefinitely
+    timeout: Option<std::time::Duration>,
+    /// Extra attempts after a spawn failure or non-zero exit
+    retries: u32,
+    /// Set on the hook process on top of the inherited environment
+    env: Vec<(String, String)>,
+    verbosity: Verbosity,
+    /// Echo the hook's stdout as it is produced instead of only after exit
+    stream: bool,
+    /// Sent in the wrapper and expected back from hooks that report a version
+    protocol_version: u32,
+    /// Restricts the orchestrator to these adapters; empty uses config.json's enabled_adapters
+    adapters: Vec<String>,
+    /// --set values, also sent to the hook to merge over its config.json
+    config_overrides: Vec<(String, serde_json::Value)>,
+    /// Where config.json and plugin.json are read from; empty means the current directory
+    config_dir: std::path::PathBuf,
+    /// Review stages in order: config.json's `review_stages`, or STAGES
+    stages: Vec<String>,
+}
 
-                if let Some(msg) = parsed.system_message {
-                    println!("\n{}", "System Message:".yellow());
-                    for line in msg.lines() {
-                        println!("  {}", line);
-                    }
+/// Why a hook call failed
+#[derive(Debug)]
+enum HookError {
+    /// No usable Python interpreter was found at startup
+    PythonNotFound(String),
+    /// The --script path doesn't exist, so there is nothing to run
+    ScriptNotFound(std::path::PathBuf),
+    SpawnFailed { python: String, source: std::io::Error },
+    WriteFailed(std::io::Error),
+    WaitFailed(std::io::Error),
+    NonZeroExit { code: Option<i32>, stderr: String },
+    Timeout(std::time::Duration),
+    /// The hook exited cleanly but its stdout is not a usable HookOutput
+    InvalidOutput(String),
+    /// The hook's stdout is not UTF-8, so it can't be JSON at all
+    InvalidUtf8 { bytes: usize },
+    /// The hook answered with an `error` field instead of a review (--serve)
+    Reported(String),
+    /// Still failing after --retries; holds the last attempt's error
+    Exhausted { attempts: u32, last: Box<HookError> },
+}
+
+impl std::fmt::Display for HookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HookError::PythonNotFound(reason) => write!(f, "{}", reason),
+            HookError::ScriptNotFound(script) => {
+                let cwd = std::env::current_dir().map(|d| d.display().to_string()).unwrap_or_default();
+                if script.is_absolute() {
+                    write!(f, "{} not found", script.display())?;
+                } else {
+                    write!(f, "{} not found in {}", script.display(), cwd)?;
                 }
-            } else {
-                println!("  Raw output: {}", output);
+                write!(f, "; run 'hook-test init' or pass --script <path>")
             }
-        }
-        Err(e) => {
-            println!("\n{} {}", "Error:".red().bold(), e);
+            HookError::SpawnFailed { python, source } => {
+                write!(f, "Failed to start Python ({}): {}", python, source)
+            }
+            HookError::WriteFailed(e) => write!(f, "Failed to write to stdin: {}", e),
+            HookError::WaitFailed(e) => write!(f, "Failed to wait for process: {}", e),
+            HookError::NonZeroExit { code: Some(code), stderr } => {
+                write!(f, "Hook failed (exit {}): {}", code, stderr)
+            }
+            HookError::NonZeroExit { code: None, stderr } => write!(f, "Hook failed: {}", stderr),
+            HookError::Timeout(timeout) => write!(f, "Hook timed out after {}s", timeout.as_secs()),
+            HookError::InvalidOutput(e) => write!(f, "Unparseable hook output: {}", e),
+            HookError::InvalidUtf8 { bytes } => write!(f, "Hook output was not valid UTF-8 ({} bytes)", bytes),
+            HookError::Reported(e) => write!(f, "Hook reported an error: {}", e),
+            HookError::Exhausted { attempts, last } => write!(f, "{} (after {} attempts)", last, attempts),
         }
     }
 }
 
-fn call_python_hook(stage: &str, input: &serde_json::Value) -> Result<String, String> {
-    let wrapper_input = serde_json::json!({
-        "stage": stage,
-        "hook_input": input
-    });
+impl HookError {
+    fn is_timeout(&self) -> bool {
+        match self {
+            HookError::Timeout(_) => true,
+            HookError::Exhausted { last, .. } => last.is_timeout(),
+            _ => false,
+        }
+    }
 
-    let mut child = Command::new("python")
-        .arg("review_orchestrator.py")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to start Python: {}", e))?;
+    /// Process exit status for Invoke when the call fails this way
+    fn exit_code(&self) -> i32 {
+        if self.is_timeout() { EXIT_TIMEOUT } else { EXIT_HOOK_ERROR }
+    }
+
+    /// Whether another attempt might succeed; a missing interpreter or script
+    /// won't appear between retries, and a timed-out hook would just time out again
+    fn is_transient(&self) -> bool {
+        matches!(self, HookError::SpawnFailed { .. } | HookError::NonZeroExit { .. })
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match (cli.no_color, cli.color) {
+        (true, _) | (false, ColorChoice::Never) => colored::control::set_override(false),
+        (false, ColorChoice::Always) => colored::control::set_override(true),
+        (false, ColorChoice::Auto) => {}
+    }
+    // Structured diagnostics go to stderr, and only when RUST_LOG asks for them
+    if std::env::var_os("RUST_LOG").is_some() {
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .with_writer(std::io::stderr)
+            .with_ansi(colored::control::SHOULD_COLORIZE.should_colorize())
+            .init();
+    }
+    install_interrupt_handler();
+    let masker = PathMasker::new(cli.mask_paths);
+    let show_diff = !cli.quiet;
+    let config_dir = cli.config_dir.as_deref().map(std::path::PathBuf::from).unwrap_or_default();
+    let hook_opts = HookOptions {
+        show_wrapper: cli.show_wrapper,
+        show_response: cli.show_response,
+        decision_map: load_decision_map(&config_dir, &cli.set),
+        db_path: cli.db.clone(),
+        python: LazyPython::new(cli.python.clone()),
+        script: cli.script.clone(),
+        timeout: None,
+        retries: 0,
+        env: Vec::new(),
+        verbosity: match (cli.quiet, cli.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        },
+        stream: false,
+        protocol_version: cli.protocol_version,
+        adapters: Vec::new(),
+        config_overrides: cli.set.clone(),
+        stages: load_review_stages(&config_dir, &cli.set),
+        config_dir,
+    };
 
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(wrapper_input.to_string().as_bytes())
-            .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+    if let Some(stage) = cli.command.stage().filter(|stage| !hook_opts.stages.iter().any(|s| s == stage)) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::InvalidValue,
+                format!("unknown stage '{}'; valid stages: {}", stage, hook_opts.stages.join(", ")),
+            )
+            .exit();
     }
 
-    let output = child
-        .wait_with_output()
-        .map_err(|e| format!("Failed to wait for process: {}", e))?;
+    if cli.verbose && matches!(cli.command, Commands::Invoke(_) | Commands::Status { .. }) {
+        eprintln!("{}", "Effective config:".dimmed());
+        let config = effective_config(&hook_opts.config_dir, &cli.set);
+        eprintln!("{}", serde_json::to_string_pretty(&config).unwrap());
+    }
 
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Hook failed: {}", stderr))
+    match cli.command {
+        Commands::Bench(args) => {
+            let hook_opts = HookOptions {
+                retries: args.retries,
+                env: args.env.clone(),
+                timeout: Some(std::time::Duration::from_secs(args.timeout)),
+                ..hook_opts.clone()
+            };
+            if args.dry_run {
+                let input = bench_input(1, "fn new() {}");
+                if let Err(e) = print_dry_run("code", &input, &hook_opts, &masker) {
+                    eprintln!("{} {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                }
+                if args.persistent {
+                    println!("  (with --persistent: plus --serve, one such request per line)");
+                }
+            } else if args.size_sweep.is_empty() {
+                run_benchmark(&args, &hook_opts)
+            } else {
+                run_size_sweep(args.iterations, args.real, &args.size_sweep, &hook_opts)
+            }
+        }
+        // Edit diffs are decoration like the banners, so --quiet drops them
+        Commands::Simulate(args) => match (&args.batch, args.tool.clone()) {
+            (Some(batch), _) => {
+                let real = args.real.then_some(&hook_opts);
+                let templates = load_templates_or_exit(args.templates.as_deref());
+                run_batch_simulation(batch, &args, &templates, real, show_diff, &masker)
+            }
+            (None, Some(tool)) => {
+                let todos = args.todos_file.as_deref().map(|path| {
+                    if tool != "TodoWrite" {
+                        eprintln!("{} --todos-file only applies to the TodoWrite tool", "Error:".red().bold());
+                        std::process::exit(1);
+                    }
+                    load_todos(path).unwrap_or_else(|e| {
+                        eprintln!("{} {}: {}", "Error:".red().bold(), path.display(), e);
+                        std::process::exit(1);
+                    })
+                });
+                let real = args.real.then_some(&hook_opts);
+                let templates = load_templates_or_exit(args.templates.as_deref());
+                let input = build_hook_input(&tool, args.file.clone(), &args.overrides, &templates);
+                let canned = CannedOutput { stage: &args.stage, message: &args.message };
+                run_simulation(input, todos, canned, real, show_diff, &masker)
+            }
+            (None, None) => unreachable!("clap requires --tool without --batch"),
+        },
+        Commands::Invoke(args) => {
+            let code = invoke_hook(args, &hook_opts, &masker);
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Status { json: false } => show_status(&hook_opts),
+        Commands::Status { json: true } => println!("{}", status_json(&hook_opts)),
+        Commands::ListAdapters => list_adapters(&hook_opts),
+        Commands::Init { force } => {
+            if !init_project(&hook_opts.config_dir, force) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Generate(args) => generate_test_files(&args, cli.seed),
+        Commands::Replay { transcript, stage } => replay_transcript(&transcript, &stage, &hook_opts, &masker),
+        Commands::Scenario { file, real, templates, overrides } => {
+            let templates = load_templates_or_exit(templates.as_deref());
+            run_scenario(&file, &overrides, &templates, real.then_some(&hook_opts), show_diff, &masker)
+        }
+        Commands::Watch { path, stage, watch_debounce, watch_ignore } => {
+            let mut ignore = load_hookignore(&hook_opts.config_dir);
+            ignore.extend(watch_ignore);
+            let debounce = std::time::Duration::from_millis(watch_debounce);
+            watch_path(&path, &stage, debounce, &ignore, &hook_opts, &masker)
+        }
+        Commands::Score { dir, stage, expect, snapshot } => {
+            if !score_corpus(&dir, &stage, expect, &snapshot, &hook_opts, &masker) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Fuzz { iterations, stage } => fuzz_hook(iterations, cli.seed, &stage, &hook_opts, &masker),
+        Commands::Profile { stage } => {
+            if !profile_hook(&stage, &hook_opts, &masker) {
+                std::process::exit(1);
+            }
+        }
+        Commands::CompareStages { file } => compare_stages(&file, &hook_opts, &masker),
+        Commands::TestSuite { dir, snapshot } => {
+            if !run_test_suite(&dir, &snapshot, &hook_opts, &masker) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Diff { baseline, current } => {
+            if !diff_saved_results(&baseline, &current) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Query { query } => match &cli.db {
+            Some(path) => {
+                if let Err(e) = db::run_query(path, query) {
+                    eprintln!("{} {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                eprintln!("{} query requires --db <PATH.sqlite>", "Error:".red().bold());
+                std::process::exit(1);
+            }
+        },
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        }
+    }
+}
+
+fn run_benchmark(args: &BenchArgs, hook_opts: &HookOptions) {
+    let BenchArgs { iterations, real, format, warmup, threads, persistent, .. } = *args;
+    let text = format == OutputFormat::Text;
+    let decorate = text && hook_opts.verbosity != Verbosity::Quiet;
+
+    if decorate {
+        println!("{}", "=== Hook System Benchmark ===".cyan().bold());
+        if warmup > 0 {
+            println!("Warmup: {} iterations (excluded)", warmup);
+        }
+        if persistent {
+            println!("Persistent: one hook process per thread, spawn time excluded");
+        }
+        if threads > 1 {
+            println!("Running {} iterations on {} threads (real: {})...\n", iterations, threads, real);
+        } else {
+            println!("Running {} iterations (real: {})...\n", iterations, real);
+        }
+    }
+
+    // One slot per worker; with --persistent each holds that worker's long-lived hook
+    let mut hooks: Vec<Option<PersistentHook>> = (0..threads).map(|_| None).collect();
+    if persistent {
+        for slot in &mut hooks {
+            match PersistentHook::spawn(hook_opts) {
+                Ok(hook) => *slot = Some(hook),
+                Err(e @ HookError::ScriptNotFound(_)) => {
+                    eprintln!("{} {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} {} (--persistent needs a hook that supports --serve)",
+                        "Error:".red().bold(),
+                        e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    for i in 1..=warmup {
+        if persistent {
+            // Every worker's process gets warmed, not just the first
+            for slot in &mut hooks {
+                let _ = persistent_iteration(i, slot, "fn new() {}", hook_opts);
+            }
+        } else {
+            let _ = bench_iteration(i, real, "fn new() {}", hook_opts);
+        }
+    }
+    let startup = if real && !persistent { measure_python_startup(hook_opts) } else { None };
+
+    let start = Instant::now();
+    let samples: std::sync::Mutex<Vec<BenchSample>> = std::sync::Mutex::new(Vec::new());
+    let print_lock = std::sync::Mutex::new(());
+
+    // Worker t takes iterations t+1, t+1+threads, ... so each keeps a distinct session_id
+    std::thread::scope(|scope| {
+        for (t, slot) in (0..threads).zip(hooks.iter_mut()) {
+            let (samples, print_lock) = (&samples, &print_lock);
+            scope.spawn(move || {
+                for i in (t + 1..=iterations).step_by(threads as usize) {
+                    let iter_start = Instant::now();
+
+                    let (result, status) = if persistent {
+                        (persistent_iteration(i, slot, "fn new() {}", hook_opts), None)
+                    } else {
+                        bench_iteration(i, real, "fn new() {}", hook_opts)
+                    };
+                    if decorate {
+                        let _guard = print_lock.lock().unwrap();
+                        print_progress(result.is_ok());
+                    }
+
+                    let elapsed = iter_start.elapsed();
+                    samples.lock().unwrap().push(BenchSample {
+                        iteration: i,
+                        duration: elapsed,
+                        success: result.is_ok(),
+                        timed_out: result.as_ref().is_err_and(HookError::is_timeout),
+                        invalid_output: matches!(result, Err(HookError::InvalidOutput(_))),
+                        status,
+                    });
+                }
+            });
+        }
+    });
+    if decorate {
+        println!();
+    }
+
+    let total = start.elapsed();
+    let mut samples = samples.into_inner().unwrap();
+    samples.sort_by_key(|s| s.iteration);
+    let timings: Vec<std::time::Duration> = samples.iter().map(|s| s.duration).collect();
+    // Timed-out iterations stay in the stats at the duration they were killed after
+    let timeouts = samples.iter().filter(|s| s.timed_out).count();
+    let failures = samples.iter().filter(|s| !s.success).count();
+    let invalid_outputs = samples.iter().filter(|s| s.invalid_output).count();
+    let avg = timings.iter().sum::<std::time::Duration>() / iterations;
+    // Wall-clock throughput, so concurrent threads show their aggregate rate
+    let throughput = iterations as f64 / total.as_secs_f64();
+
+    // Calculate stats
+    let min = timings.iter().min().unwrap();
+    let max = timings.iter().max().unwrap();
+
+    let mut sorted = timings.clone();
+    sorted.sort();
+    let p50 = percentile(&sorted, 50.0);
+    let p95 = percentile(&sorted, 95.0);
+    let p99 = percentile(&sorted, 99.0);
+
+    if format == OutputFormat::Json {
+        let ms = |d: &std::time::Duration| d.as_secs_f64() * 1000.0;
+        let summary = serde_json::json!({
+            "iterations": iterations,
+            "warmup": warmup,
+            "threads": threads,
+            "real": real,
+            "persistent": persistent,
+            "total_ms": ms(&total),
+            "avg_ms": ms(&avg),
+            "min_ms": ms(min),
+            "max_ms": ms(max),
+            "p50_ms": ms(&p50),
+            "p95_ms": ms(&p95),
+            "p99_ms": ms(&p99),
+            "throughput_per_sec": throughput,
+            "failures": failures,
+            "invalid_outputs": invalid_outputs,
+            "timeouts": timeouts,
+            "startup_ms": startup.as_ref().map(ms),
+            "avg_minus_startup_ms": startup.map(|s| ms(&avg.saturating_sub(s))),
+            "timings_ms": timings.iter().map(ms).collect::<Vec<_>>(),
+            "samples": samples
+                .iter()
+                .map(|s| serde_json::json!({
+                    "iteration": s.iteration,
+                    "ms": ms(&s.duration),
+                    "success": s.success,
+                    "timed_out": s.timed_out,
+                    "invalid_output": s.invalid_output,
+                    "process": s.status.as_ref().map(process_status_json),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", summary);
+    } else {
+        let mut rows = vec![("Total time", format_ms(total)), ("Average", format_ms(avg))];
+        if let Some(startup) = startup {
+            rows.push(("Python startup", format_ms(startup)));
+            rows.push(("Average minus startup", format_ms(avg.saturating_sub(startup))));
+        }
+        rows.extend([
+            ("Min", format_ms(*min)),
+            ("Max", format_ms(*max)),
+            ("P50", format_ms(p50)),
+            ("P95", format_ms(p95)),
+            ("P99", format_ms(p99)),
+        ]);
+        if threads > 1 {
+            rows.push(("Threads", threads.to_string()));
+        }
+        if failures > 0 {
+            rows.push(("Failed", failures.to_string().red().to_string()));
+        }
+        if invalid_outputs > 0 {
+            rows.push(("Unparseable", invalid_outputs.to_string().red().to_string()));
+        }
+        if timeouts > 0 {
+            rows.push(("Timed out", timeouts.to_string().red().to_string()));
+        }
+        if total.as_millis() > 0 {
+            rows.push(("Throughput", format!("{:.2} calls/sec", throughput)));
+        }
+
+        println!("\n{}", "Results:".green().bold());
+        print_stat_table(&rows);
+        if args.histogram {
+            print_histogram(&sorted);
+        }
+        if startup.is_some() {
+            println!(
+                "\n{}",
+                format!("Note: startup is approximate (mean of {} `python -c pass` runs)", STARTUP_SAMPLES)
+                    .dimmed()
+            );
+        }
+    }
+
+    if let Some(path) = &args.csv {
+        if let Err(e) = write_bench_csv(path, &samples) {
+            eprintln!("{} Failed to write {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+        if text {
+            println!("\n{} {}", "Timings written to".green(), path.display());
+        }
+    }
+
+    if let Some(path) = &args.prometheus {
+        let summary = BenchSummary {
+            iterations: samples.len(),
+            failures,
+            timeouts,
+            sorted: &sorted,
+            total,
+            throughput,
+        };
+        if let Err(e) = write_bench_prometheus(path, &summary) {
+            eprintln!("{} Failed to write {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+        if text {
+            println!("\n{} {}", "Metrics written to".green(), path.display());
+        }
+    }
+
+    let avg_ms = avg.as_secs_f64() * 1000.0;
+    // Read the baseline before appending, in case --history points at the same file
+    let baseline_ms = args.baseline.as_deref().and_then(latest_history_avg);
+
+    if let Some(path) = &args.history {
+        let entry = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "iterations": iterations,
+            "warmup": warmup,
+            "threads": threads,
+            "real": real,
+            "avg_ms": avg_ms,
+            "p50_ms": p50.as_secs_f64() * 1000.0,
+            "p95_ms": p95.as_secs_f64() * 1000.0,
+            "p99_ms": p99.as_secs_f64() * 1000.0,
+            "throughput_per_sec": throughput,
+        });
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            eprintln!("{} Failed to write {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+        if text {
+            println!("\n{} {}", "Summary appended to".green(), path.display());
+        }
+    }
+
+    if let Some(path) = &args.baseline {
+        let Some(baseline_ms) = baseline_ms else {
+            if text {
+                println!("\n{} {} has no entries yet; nothing to compare", "Baseline:".yellow(), path.display());
+            }
+            return;
+        };
+        let change = (avg_ms - baseline_ms) / baseline_ms * 100.0;
+        let report = format!(
+            "{:.2}ms vs baseline {:.2}ms ({:+.1}%, threshold {}%)",
+            avg_ms, baseline_ms, change, args.regression_threshold
+        );
+        if change > args.regression_threshold {
+            eprintln!("\n{} {}", "❌ REGRESSION:".red().bold(), report);
+            std::process::exit(1);
+        }
+        if text {
+            println!("\n{} {}", "✅ Within baseline:".green(), report);
+        }
+    }
+}
+
+/// Formats a duration as milliseconds with two decimals, e.g. `12.34 ms`
+fn format_ms(d: std::time::Duration) -> String {
+    format!("{:.2} ms", d.as_secs_f64() * 1000.0)
+}
+
+/// Prints label / value rows with the values lined up; millisecond values are
+/// right-aligned so their decimal points match
+fn print_stat_table(rows: &[(&str, String)]) {
+    let is_ms = |value: &String| value.ends_with(" ms");
+    let label_width = rows.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+    let value_width = rows.iter().filter(|(_, v)| is_ms(v)).map(|(_, v)| v.len()).max().unwrap_or(0);
+    for (label, value) in rows {
+        if is_ms(value) {
+            println!("  {:<label_width$}  {:>value_width$}", label, value);
+        } else {
+            println!("  {:<label_width$}  {}", label, value);
+        }
+    }
+}
+
+/// Number of bare interpreter launches averaged into the startup estimate
+const STARTUP_SAMPLES: u32 = 5;
+
+/// Mean wall time of `python -c pass`, i.e. what each real iteration pays before the hook runs
+fn measure_python_startup(hook_opts: &HookOptions) -> Option<std::time::Duration> {
+    let python = hook_opts.python.get().as_ref().ok()?;
+    let mut total = std::time::Duration::ZERO;
+    for _ in 0..STARTUP_SAMPLES {
+        let start = Instant::now();
+        let status = python
+            .command()
+            .args(["-c", "pass"])
+            .envs(hook_opts.env.iter().map(|(k, v)| (k, v)))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok()?;
+        if !status.success() {
+            return None;
+        }
+        total += start.elapsed();
+    }
+    Some(total / STARTUP_SAMPLES)
+}
+
+/// Average of the most recent valid entry in a --history file, if there is one
+fn latest_history_avg(path: &std::path::Path) -> Option<f64> {
+    let content = std::fs::read_to_string(path).ok()?;
+    content
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find_map(|entry| entry["avg_ms"].as_f64())
+        .filter(|avg| *avg > 0.0)
+}
+
+/// Number of equal-width bins in the --histogram chart
+const HISTOGRAM_BINS: usize = 10;
+/// Width in characters of the longest --histogram bar
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Buckets sorted timings into equal-width bins between min and max and draws one bar per bin
+fn print_histogram(sorted: &[std::time::Duration]) {
+    let ms: Vec<f64> = sorted.iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+    let (min, max) = (ms[0], ms[ms.len() - 1]);
+    let bins = if max > min { HISTOGRAM_BINS } else { 1 };
+    let step = (max - min) / bins as f64;
+
+    let mut counts = vec![0usize; bins];
+    for value in &ms {
+        let bin = if step > 0.0 { ((value - min) / step) as usize } else { 0 };
+        counts[bin.min(bins - 1)] += 1;
+    }
+    let peak = counts.iter().copied().max().unwrap_or(1);
+
+    println!("\n{}", "Latency Histogram:".green().bold());
+    for (i, count) in counts.iter().enumerate() {
+        let low = min + step * i as f64;
+        let high = if i + 1 == bins { max } else { low + step };
+        let bar = "█".repeat((count * HISTOGRAM_WIDTH).div_ceil(peak));
+        println!("  {:>9.2} – {:>9.2} ms │{} {}", low, high, bar.cyan(), count);
+    }
+}
+
+fn write_bench_csv(path: &std::path::Path, samples: &[BenchSample]) -> std::io::Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "iteration,duration_ms,success")?;
+    for sample in samples {
+        writeln!(
+            writer,
+            "{},{:.3},{}",
+            sample.iteration,
+            sample.duration.as_secs_f64() * 1000.0,
+            sample.success
+        )?;
+    }
+    writer.flush()
+}
+
+/// What --prometheus reports about one run
+struct BenchSummary<'a> {
+    iterations: usize,
+    failures: usize,
+    timeouts: usize,
+    sorted: &'a [std::time::Duration],
+    total: std::time::Duration,
+    throughput: f64,
+}
+
+/// Writes the run as Prometheus text exposition format. The file is written
+/// beside the target and renamed into place so a scrape never sees half of it.
+fn write_bench_prometheus(path: &std::path::Path, summary: &BenchSummary) -> std::io::Result<()> {
+    let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+    let sum: std::time::Duration = summary.sorted.iter().sum();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for (suffix, value) in samples {
+            out.push_str(&format!("{}{} {}\n", name, suffix, value));
+        }
+    };
+
+    metric(
+        "hook_latency_ms",
+        "summary",
+        "Per-iteration hook latency in milliseconds.",
+        &[
+            ("{quantile=\"0.5\"}", ms(percentile(summary.sorted, 50.0))),
+            ("{quantile=\"0.95\"}", ms(percentile(summary.sorted, 95.0))),
+            ("{quantile=\"0.99\"}", ms(percentile(summary.sorted, 99.0))),
+            ("_sum", ms(sum)),
+            ("_count", summary.sorted.len() as f64),
+        ],
+    );
+    let min = summary.sorted.first().copied().unwrap_or_default();
+    let max = summary.sorted.last().copied().unwrap_or_default();
+    metric("hook_latency_min_ms", "gauge", "Fastest iteration in milliseconds.", &[("", ms(min))]);
+    metric("hook_latency_max_ms", "gauge", "Slowest iteration in milliseconds.", &[("", ms(max))]);
+    metric("hook_bench_duration_ms", "gauge", "Wall-clock time of the whole run in milliseconds.", &[
+        ("", ms(summary.total)),
+    ]);
+    metric("hook_throughput_calls_per_sec", "gauge", "Completed iterations per wall-clock second.", &[
+        ("", summary.throughput),
+    ]);
+    metric("hook_iterations_total", "counter", "Measured iterations, excluding warmup.", &[
+        ("", summary.iterations as f64),
+    ]);
+    metric("hook_failures_total", "counter", "Iterations whose hook call failed.", &[
+        ("", summary.failures as f64),
+    ]);
+    metric("hook_timeouts_total", "counter", "Iterations killed for exceeding --timeout.", &[
+        ("", summary.timeouts as f64),
+    ]);
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, out)?;
+    std::fs::rename(&temp, path)
+}
+
+/// The Edit every benchmark iteration sends
+fn bench_input(i: u32, new_string: &str) -> serde_json::Value {
+    edit_hook_input(&format!("bench-{}", i), "bench_test.rs", "fn old() {}", new_string)
+}
+
+/// The working directory sent as `cwd`, read once; "." if it can't be read (e.g. it was deleted)
+fn working_dir() -> &'static str {
+    static CWD: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    CWD.get_or_init(|| {
+        std::env::current_dir()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| ".".to_string())
+    })
+}
+
+/// Hook input for an Edit of `file_path` replacing `old` with `new`
+fn edit_hook_input(session_id: &str, file_path: &str, old: &str, new: &str) -> serde_json::Value {
+    serde_json::json!({
+        "session_id": session_id,
+        "tool_name": "Edit",
+        "tool_input": {
+            "file_path": file_path,
+            "old_string": old,
+            "new_string": new
+        },
+        "cwd": working_dir()
+    })
+}
+
+/// Runs one benchmark iteration, returning whether the hook call succeeded and,
+/// when a hook process ran to completion, how it exited
+fn bench_iteration(
+    i: u32,
+    real: bool,
+    new_string: &str,
+    hook_opts: &HookOptions,
+) -> (Result<(), HookError>, Option<std::process::ExitStatus>) {
+    if !real {
+        // Simulate hook call delay
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        return (Ok(()), None);
+    }
+
+    // Actually call the Python hook
+    let output = match run_python_hook("code", &bench_input(i, new_string), hook_opts) {
+        Ok(output) => output,
+        Err(e) => return (Err(e), None),
+    };
+    let result = if !output.status.success() {
+        Err(HookError::NonZeroExit {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim_end().to_string(),
+        })
+    } else {
+        match std::str::from_utf8(&output.stdout) {
+            Ok(stdout) => parse_hook_output(stdout, false).map(|_| ()).map_err(HookError::InvalidOutput),
+            Err(_) => Err(HookError::InvalidUtf8 { bytes: output.stdout.len() }),
+        }
+    };
+    (result, Some(output.status))
+}
+
+/// One --persistent iteration. A process that died or timed out is dropped and
+/// a fresh one spawned on the next iteration, so one crash fails one request;
+/// an unusable or `error` response fails the iteration but keeps the process.
+fn persistent_iteration(
+    i: u32,
+    slot: &mut Option<PersistentHook>,
+    new_string: &str,
+    hook_opts: &HookOptions,
+) -> Result<(), HookError> {
+    let hook = match slot {
+        Some(hook) => hook,
+        None => slot.insert(PersistentHook::spawn(hook_opts)?),
+    };
+    let wrapper = hook_wrapper("code", &bench_input(i, new_string), hook_opts);
+    let response = hook.request(&wrapper, hook_opts.timeout);
+    if response.is_err() {
+        *slot = None;
+    }
+    parse_served_response(&response?).map(|_| ())
+}
+
+/// A `--serve` response line: `{"error": ...}` when the hook couldn't handle the request, otherwise a HookOutput
+fn parse_served_response(line: &str) -> Result<HookOutput, HookError> {
+    let value = serde_json::from_str::<serde_json::Value>(line).ok();
+    if let Some(error) = value.as_ref().and_then(|value| value.get("error")) {
+        let error = error.as_str().map(String::from).unwrap_or_else(|| error.to_string());
+        return Err(HookError::Reported(error));
+    }
+    parse_hook_output(line, false).map_err(HookError::InvalidOutput)
+}
+
+fn print_progress(ok: bool) {
+    if ok {
+        print!("{}", ".".green());
+    } else {
+        print!("{}", "x".red());
+    }
+    let _ = std::io::stdout().flush();
+}
+
+fn run_size_sweep(iterations: u32, real: bool, sizes: &[usize], hook_opts: &HookOptions) {
+    println!("{}", "=== Hook System Benchmark (size sweep) ===".cyan().bold());
+    println!("Running {} iterations per size (real: {})...\n", iterations, real);
+
+    let mut rows = Vec::new();
+    for &size in sizes {
+        let payload = generate_padded_code(size);
+        print!("  {:>10} bytes ", size);
+
+        let mut timings: Vec<std::time::Duration> = Vec::new();
+        for i in 1..=iterations {
+            let iter_start = Instant::now();
+            print_progress(bench_iteration(i, real, &payload, hook_opts).0.is_ok());
+            timings.push(iter_start.elapsed());
+        }
+        println!();
+
+        timings.sort();
+        let avg = timings.iter().sum::<std::time::Duration>() / iterations.max(1);
+        rows.push((size, avg, percentile(&timings, 95.0)));
+    }
+
+    println!("\n{}", "Results:".green().bold());
+    println!("  {:>12}  {:>12}  {:>12}", "Size (bytes)", "Avg", "P95");
+    for (size, avg, p95) in rows {
+        println!(
+            "  {:>12}  {:>9.2} ms  {:>9.2} ms",
+            size,
+            avg.as_secs_f64() * 1000.0,
+            p95.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Nearest-rank percentile of an already sorted slice
+fn percentile(sorted: &[std::time::Duration], pct: f64) -> std::time::Duration {
+    if sorted.is_empty() {
+        return std::time::Duration::ZERO;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Parses sizes like `512`, `10k` or `2m` into a byte count
+fn parse_byte_size(raw: &str) -> Result<usize, String> {
+    let lower = raw.trim().to_lowercase();
+    let (digits, multiplier) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 1024),
+        Some('m') => (&lower[..lower.len() - 1], 1024 * 1024),
+        _ => (lower.as_str(), 1),
+    };
+    digits
+        .parse::<usize>()
+        .map(|n| n * multiplier)
+        .map_err(|_| format!("invalid size '{}' (expected e.g. 512, 10k, 2m)", raw))
+}
+
+/// Parses `KEY=VALUE`; the key must be a valid environment variable name
+fn parse_env_var(raw: &str) -> Result<(String, String), String> {
+    let (key, value) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("invalid env '{}' (expected KEY=VALUE)", raw))?;
+    let valid_key = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_key {
+        return Err(format!("invalid env name '{}' (expected letters, digits and '_')", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+const TODO_STATUSES: [&str; 3] = ["pending", "in_progress", "completed"];
+
+/// Reads a TodoWrite list, naming the first entry that isn't a `{content, status}` object
+fn load_todos(path: &std::path::Path) -> Result<serde_json::Value, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("unreadable ({})", e))?;
+    let value: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("invalid JSON at line {} column {}", e.line(), e.column()))?;
+
+    let Some(todos) = value.as_array() else {
+        return Err("top level must be an array of todos".to_string());
+    };
+    for (index, todo) in todos.iter().enumerate() {
+        let Some(obj) = todo.as_object() else {
+            return Err(format!("todo #{} must be an object", index + 1));
+        };
+        match obj.get("content").and_then(|c| c.as_str()) {
+            Some(content) if !content.trim().is_empty() => {}
+            Some(_) => return Err(format!("todo #{} has an empty 'content'", index + 1)),
+            None => return Err(format!("todo #{} is missing a string 'content'", index + 1)),
+        }
+        match obj.get("status").and_then(|s| s.as_str()) {
+            Some(status) if TODO_STATUSES.contains(&status) => {}
+            Some(status) => {
+                return Err(format!(
+                    "todo #{} has status '{}' (expected {})",
+                    index + 1,
+                    status,
+                    TODO_STATUSES.join(", ")
+                ))
+            }
+            None => return Err(format!("todo #{} is missing a string 'status'", index + 1)),
+        }
+    }
+
+    Ok(value)
+}
+
+fn parse_existing_dir(raw: &str) -> Result<String, String> {
+    if std::path::Path::new(raw).is_dir() {
+        Ok(raw.to_string())
+    } else {
+        Err(format!("'{}' is not an existing directory", raw))
+    }
+}
+
+/// Builds syntactically plausible Rust code of exactly `size` bytes
+fn generate_padded_code(size: usize) -> String {
+    let mut code = String::with_capacity(size + 64);
+    let mut n = 0;
+    while code.len() < size {
+        code.push_str(&format!("fn pad_{}() -> u32 {{ {} }}\n", n, n));
+        n += 1;
+    }
+    code.truncate(size);
+    code
+}
+
+/// The stage a simulation runs at and the message its canned output carries
+#[derive(Clone, Copy)]
+struct CannedOutput<'a> {
+    stage: &'a str,
+    /// Template with `{stage}` placeholders
+    message: &'a str,
+}
+
+impl CannedOutput<'_> {
+    fn message(&self) -> String {
+        self.message.replace("{stage}", self.stage)
+    }
+}
+
+fn run_simulation(
+    mut hook_input: HookInput,
+    todos: Option<serde_json::Value>,
+    canned: CannedOutput,
+    real: Option<&HookOptions>,
+    show_diff: bool,
+    masker: &PathMasker,
+) {
+    println!("{}", format!("=== Simulating {} Tool ===", hook_input.tool_name).cyan().bold());
+
+    if let Some(todos) = todos {
+        hook_input.tool_input["todos"] = todos;
+    }
+    print_simulation(&hook_input, canned, real, show_diff, masker);
+}
+
+fn run_batch_simulation(
+    path: &std::path::Path,
+    args: &SimulateArgs,
+    templates: &ToolTemplates,
+    real: Option<&HookOptions>,
+    show_diff: bool,
+    masker: &PathMasker,
+) {
+    println!("{}", format!("=== Simulating Batch ({}) ===", path.display()).cyan().bold());
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let (entries, skipped) = match parse_batch(&content, args.abort_on_parse_error) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("\n{} {}", "Aborted:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    // Per tool: [continue, block, warn, error]
+    let mut tally: std::collections::BTreeMap<&str, [usize; 4]> = std::collections::BTreeMap::new();
+    let mut simulated = 0;
+    let start = Instant::now();
+
+    for pass in 1..=args.count {
+        for (line_no, entry) in &entries {
+            simulated += 1;
+            let pass_label = if args.count > 1 { format!(", pass {}/{}", pass, args.count) } else { String::new() };
+            println!(
+                "\n{}",
+                format!("--- [{}] {} (line {}{}) ---", simulated, entry.tool, line_no, pass_label).cyan()
+            );
+
+            let mut hook_input = build_hook_input(&entry.tool, entry.file.clone(), &args.overrides, templates);
+            if let Some(tool_input) = hook_input.tool_input.as_object_mut() {
+                tool_input.extend(entry.extra.clone());
+            }
+            let canned = CannedOutput { stage: &args.stage, message: &args.message };
+            let column = match print_simulation(&hook_input, canned, real, show_diff, masker) {
+                Some(Decision::Continue) => 0,
+                Some(Decision::Block) => 1,
+                Some(Decision::Warn) => 2,
+                None => 3,
+            };
+            tally.entry(entry.tool.as_str()).or_default()[column] += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!("\n{}", "Batch Summary:".green().bold());
+    if args.count > 1 {
+        println!("  Simulated: {} ({} entries × {} passes)", simulated, entries.len(), args.count);
+    } else {
+        println!("  Simulated: {}", simulated);
+    }
+    if skipped > 0 {
+        println!("  Skipped (invalid): {}", skipped.to_string().red());
+    }
+    println!("  Elapsed: {}", format_ms(elapsed));
+    if tally.is_empty() {
+        return;
+    }
+
+    let width = tally.keys().map(|tool| tool.chars().count()).max().unwrap_or(0).max("Total".len());
+    println!(
+        "\n  {:<width$}  {:>5}  {:>8}  {:>5}  {:>4}  {:>5}",
+        "Tool", "Calls", "Continue", "Block", "Warn", "Error"
+    );
+    let mut totals = [0usize; 4];
+    let row = |tool: &str, counts: &[usize; 4]| {
+        println!(
+            "  {:<width$}  {:>5}  {:>8}  {:>5}  {:>4}  {:>5}",
+            tool,
+            counts.iter().sum::<usize>(),
+            counts[0],
+            counts[1],
+            counts[2],
+            counts[3]
+        );
+    };
+    for (tool, counts) in &tally {
+        row(tool, counts);
+        for (total, count) in totals.iter_mut().zip(counts) {
+            *total += count;
+        }
+    }
+    row("Total", &totals);
+}
+
+/// Parses a --batch file into (line number, entry) pairs plus the count of
+/// malformed entries skipped; with `abort`, the first malformed one is an error
+fn parse_batch(content: &str, abort: bool) -> Result<(Vec<(usize, BatchEntry)>, usize), String> {
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<BatchEntry>(line) {
+            Ok(entry) => entries.push((index + 1, entry)),
+            Err(e) if abort => {
+                let entry = entries.len() + skipped + 1;
+                return Err(format!("malformed entry {} (line {}): {}", entry, index + 1, e));
+            }
+            Err(e) => {
+                println!("\n{} line {}: {}", "Skipping invalid entry at".red(), index + 1, e);
+                skipped += 1;
+            }
+        }
+    }
+    Ok((entries, skipped))
+}
+
+fn build_hook_input(
+    tool: &str,
+    file: Option<String>,
+    overrides: &InputOverrides,
+    templates: &ToolTemplates,
+) -> HookInput {
+    let cwd = overrides.cwd.clone().unwrap_or_else(|| working_dir().to_string());
+    let tool_input = match templates.get(tool) {
+        Some(template) => {
+            let file = file.as_deref().unwrap_or("test.rs");
+            fill_template(template, &[("file", file), ("cwd", &cwd)])
+        }
+        None => builtin_tool_input(tool, file),
+    };
+
+    HookInput {
+        session_id: overrides
+            .session_id
+            .clone()
+            .unwrap_or_else(|| format!("test-{}", chrono::Utc::now().timestamp())),
+        tool_name: tool.to_string(),
+        tool_input,
+        cwd,
+        version: None,
+    }
+}
+
+/// tool_input for the tools the simulator knows; anything else gets `{}`
+fn builtin_tool_input(tool: &str, file: Option<String>) -> serde_json::Value {
+    match tool {
+        "Edit" => serde_json::json!({
+            "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
+            "old_string": "fn old() {}",
+            "new_string": "fn new() { /* TODO: implement */ }"
+        }),
+        "Write" => serde_json::json!({
+            "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
+            "content": "fn main() {\n    println!(\"test\");\n}"
+        }),
+        "MultiEdit" => serde_json::json!({
+            "file_path": file.unwrap_or_else(|| "test.rs".to_string()),
+            "edits": [
+                {"old_string": "fn old() {}", "new_string": "fn new() { /* TODO: implement */ }"},
+                {"old_string": "let x = 1;", "new_string": "let x = compute();"}
+            ]
+        }),
+        "Read" => serde_json::json!({
+            "file_path": file.unwrap_or_else(|| "test.rs".to_string())
+        }),
+        "Grep" => serde_json::json!({
+            "pattern": "TODO",
+            "path": file.unwrap_or_else(|| ".".to_string())
+        }),
+        "Glob" => serde_json::json!({
+            "pattern": "**/*.rs"
+        }),
+        "TodoWrite" => serde_json::json!({
+            "todos": [
+                {"content": "Implement feature", "status": "pending"},
+                {"content": "Write tests", "status": "pending"}
+            ]
+        }),
+        _ => serde_json::json!({}),
+    }
+}
+
+/// `--templates` file contents: tool name to a tool_input template
+type ToolTemplates = HashMap<String, serde_json::Value>;
+
+/// Reads a `{"Tool": {...tool_input...}}` templates file
+fn load_templates(path: &std::path::Path) -> Result<ToolTemplates, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("unreadable ({})", e))?;
+    let value: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("invalid JSON at line {} column {}", e.line(), e.column()))?;
+
+    let serde_json::Value::Object(tools) = value else {
+        return Err("top level must be an object of tool name to tool_input".to_string());
+    };
+    for (tool, template) in &tools {
+        if !template.is_object() {
+            return Err(format!("template for '{}' must be an object", tool));
+        }
+    }
+    Ok(tools.into_iter().collect())
+}
+
+fn load_templates_or_exit(path: Option<&std::path::Path>) -> ToolTemplates {
+    let Some(path) = path else {
+        return ToolTemplates::new();
+    };
+    load_templates(path).unwrap_or_else(|e| {
+        eprintln!("{} {}: {}", "Error:".red().bold(), path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Replaces `{{name}}` placeholders in every string of a template, keys included
+fn fill_template(template: &serde_json::Value, vars: &[(&str, &str)]) -> serde_json::Value {
+    let fill = |text: &str| {
+        vars.iter().fold(text.to_string(), |text, (name, value)| text.replace(&format!("{{{{{}}}}}", name), value))
+    };
+    match template {
+        serde_json::Value::String(text) => serde_json::Value::String(fill(text)),
+        serde_json::Value::Array(items) => items.iter().map(|item| fill_template(item, vars)).collect(),
+        serde_json::Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| (fill(key), fill_template(value, vars)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        other => other.clone(),
+    }
+}
+
+/// Prints the input, then either the canned expected output or, with `real`,
+/// the decision from actually calling the hook. Returns the decision, or None
+/// if the hook failed.
+fn print_simulation(
+    hook_input: &HookInput,
+    canned: CannedOutput,
+    real: Option<&HookOptions>,
+    show_diff: bool,
+    masker: &PathMasker,
+) -> Option<Decision> {
+    let stage = canned.stage;
+    println!("\n{}", "Hook Input:".yellow());
+    let input = serde_json::to_value(hook_input).unwrap();
+    println!("{}", serde_json::to_string_pretty(&masker.mask_json(&input)).unwrap());
+    if show_diff {
+        print_edit_diff(hook_input, masker);
+    }
+
+    if let Some(hook_opts) = real {
+        println!("\n{}", "Actual Hook Output:".yellow());
+        let result = call_python_hook(stage, &input, hook_opts)
+            .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput));
+        match result {
+            Ok(parsed) => {
+                let (decision, warning) = parsed.resolve_decision(&hook_opts.decision_map);
+                if let Some(warning) = warning {
+                    println!("  {} {}", "Warning:".yellow(), warning);
+                }
+                println!("  Decision: {}", decision.label());
+                for line in parsed.system_message.as_deref().unwrap_or("").lines() {
+                    println!("  {}", masker.mask(line));
+                }
+                return Some(decision);
+            }
+            Err(e) => {
+                println!("  {} {}", "Error:".red().bold(), masker.mask(&e.to_string()));
+                return None;
+            }
+        }
+    }
+
+    // Simulate hook response
+    let response = HookOutput {
+        should_continue: Some(true),
+        system_message: Some(canned.message()),
+        decision: None,
+        version: None,
+        issues: None,
+    };
+
+    println!("\n{}", "Expected Hook Output:".yellow());
+    println!("{}", serde_json::to_string_pretty(&response).unwrap());
+    Some(Decision::Continue)
+}
+
+/// Prints a line diff of each old_string/new_string pair of an Edit or MultiEdit
+fn print_edit_diff(hook_input: &HookInput, masker: &PathMasker) {
+    let tool_input = &hook_input.tool_input;
+    let edits: Vec<&serde_json::Value> = match hook_input.tool_name.as_str() {
+        "Edit" => vec![tool_input],
+        "MultiEdit" => tool_input["edits"].as_array().map(|e| e.iter().collect()).unwrap_or_default(),
+        _ => return,
+    };
+    let file = masker.mask(tool_input["file_path"].as_str().unwrap_or("file"));
+
+    println!("\n{}", "Diff:".yellow());
+    for (index, edit) in edits.iter().enumerate() {
+        let old = edit["old_string"].as_str().unwrap_or("");
+        let new = edit["new_string"].as_str().unwrap_or("");
+        if edits.len() > 1 {
+            println!("{}", format!("  edit {}/{}", index + 1, edits.len()).dimmed());
+        }
+        println!("{}", format!("--- a/{}", file).bold());
+        println!("{}", format!("+++ b/{}", file).bold());
+        let (old_lines, new_lines): (Vec<&str>, Vec<&str>) = (old.lines().collect(), new.lines().collect());
+        println!("{}", format!("@@ -1,{} +1,{} @@", old_lines.len(), new_lines.len()).cyan());
+        for line in diff_lines(&old_lines, &new_lines) {
+            match line {
+                DiffLine::Same(text) => println!(" {}", masker.mask(text)),
+                DiffLine::Removed(text) => println!("{}", format!("-{}", masker.mask(text)).red()),
+                DiffLine::Added(text) => println!("{}", format!("+{}", masker.mask(text)).green()),
+            }
+        }
+    }
+}
+
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Longest-common-subsequence table size beyond which diff_lines gives up and
+/// shows a full replacement instead
+const DIFF_MAX_CELLS: usize = 4_000_000;
+
+/// Line diff via the longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    if (old.len() + 1) * (new.len() + 1) > DIFF_MAX_CELLS {
+        let removed = old.iter().map(|l| DiffLine::Removed(l));
+        return removed.chain(new.iter().map(|l| DiffLine::Added(l))).collect();
+    }
+
+    // lcs[i][j] = length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|l| DiffLine::Removed(l)));
+    lines.extend(new[j..].iter().map(|l| DiffLine::Added(l)));
+    lines
+}
+
+/// Simulates each step of a scenario in order under one session_id, so the
+/// hook sees the stage transitions of a single session
+fn run_scenario(
+    path: &std::path::Path,
+    overrides: &InputOverrides,
+    templates: &ToolTemplates,
+    real: Option<&HookOptions>,
+    show_diff: bool,
+    masker: &PathMasker,
+) {
+    println!("{}", format!("=== Scenario ({}) ===", path.display()).cyan().bold());
+
+    let steps = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str::<Vec<ScenarioStep>>(&content).map_err(|e| e.to_string()))
+    {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let session_id = overrides
+        .session_id
+        .clone()
+        .unwrap_or_else(|| format!("scenario-{}", chrono::Utc::now().timestamp()));
+    println!("Session: {} ({} steps)", session_id, steps.len());
+
+    let mut outcomes = Vec::new();
+    for (index, step) in steps.into_iter().enumerate() {
+        println!(
+            "\n{}",
+            format!("--- Step {}: {} ({}) ---", index + 1, step.call.tool, step.stage).cyan()
+        );
+
+        let mut hook_input = build_hook_input(&step.call.tool, step.call.file, overrides, templates);
+        hook_input.session_id = session_id.clone();
+        if let Some(tool_input) = hook_input.tool_input.as_object_mut() {
+            tool_input.extend(step.call.extra);
+        }
+        let canned = CannedOutput { stage: &step.stage, message: SIMULATED_MESSAGE };
+        let decision = print_simulation(&hook_input, canned, real, show_diff, masker);
+        outcomes.push((step.stage, step.call.tool, decision));
+    }
+
+    println!("\n{}", "Scenario Summary:".green().bold());
+    for (index, (stage, tool, decision)) in outcomes.iter().enumerate() {
+        let label = decision.map(Decision::label).unwrap_or_else(|| "💥 ERROR".red());
+        println!("  {}. {:<6} {:<10} {}", index + 1, stage, tool, label);
+    }
+    let blocked = outcomes.iter().filter(|(_, _, d)| *d == Some(Decision::Block)).count();
+    let errors = outcomes.iter().filter(|(_, _, d)| d.is_none()).count();
+    println!(
+        "  Result: {} of {} steps passed{}{}",
+        outcomes.len() - blocked - errors,
+        outcomes.len(),
+        if blocked > 0 { format!(", {} blocked", blocked) } else { String::new() },
+        if errors > 0 { format!(", {} errors", errors) } else { String::new() },
+    );
+}
+
+const INIT_CONFIG: &str = r#"{
+  "enabled_adapters": ["gemini"],
+  "timeout_seconds": 60,
+  "parallel_execution": true
+}
+"#;
+
+const INIT_PLUGIN: &str = r#"{
+  "name": "self-review",
+  "version": "0.1.0",
+  "description": "Multi-LLM self review hooks",
+  "hooks": {
+    "PostToolUse": [
+      {
+        "matcher": "Edit|Write",
+        "hooks": [{ "type": "command", "command": "$PLUGIN_DIR/review-code.sh" }]
+      }
+    ],
+    "Stop": [
+      {
+        "hooks": [{ "type": "command", "command": "$PLUGIN_DIR/review-final.sh" }]
+      }
+    ]
+  }
+}
+"#;
+
+/// Writes the starter config.json and plugin.json into `dir`. Without `force`,
+/// nothing is written when either already exists. Returns false on failure.
+fn init_project(dir: &std::path::Path, force: bool) -> bool {
+    println!("{}", "=== Initializing Hook Project ===".cyan().bold());
+    let files = [(dir.join("config.json"), INIT_CONFIG), (dir.join("plugin.json"), INIT_PLUGIN)];
+
+    let existing: Vec<String> =
+        files.iter().filter(|(path, _)| path.exists()).map(|(path, _)| path.display().to_string()).collect();
+    if !force && !existing.is_empty() {
+        println!(
+            "\n{} {} already {}; pass --force to overwrite",
+            "Aborted:".red().bold(),
+            existing.join(" and "),
+            if existing.len() == 1 { "exists" } else { "exist" }
+        );
+        return false;
+    }
+
+    println!();
+    for (path, content) in files {
+        let name = path.display().to_string();
+        let verb = if existing.contains(&name) { "Overwrote" } else { "Created" };
+        if let Err(e) = std::fs::write(&path, content) {
+            println!("{} Failed to write {}: {}", "Error:".red().bold(), name, e);
+            return false;
+        }
+        println!("  {} {}", format!("✅ {}", verb).green(), name);
+    }
+
+    println!("\n{} run `hook-test status` to check the setup", "Next:".yellow());
+    true
+}
+
+/// The parts of plugin.json the CLI checks
+#[derive(Deserialize, Debug)]
+struct PluginManifest {
+    name: String,
+    version: String,
+    #[serde(default)]
+    description: Option<String>,
+    /// Hook event name → matchers, e.g. `"PostToolUse": [{"matcher": "Edit|Write", ...}]`
+    #[serde(default)]
+    hooks: std::collections::BTreeMap<String, Vec<PluginHookMatcher>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PluginHookMatcher {
+    #[serde(default)]
+    matcher: Option<String>,
+    hooks: Vec<PluginHookCommand>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PluginHookCommand {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+fn load_plugin_manifest(path: &std::path::Path) -> Result<PluginManifest, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("unreadable ({})", e))?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+/// The script a hook command starts, with `$PLUGIN_DIR` resolved against `dir`;
+/// None for commands that run a program from PATH
+fn hook_entry_point(command: &str, dir: &std::path::Path) -> Option<std::path::PathBuf> {
+    let program = command.split_whitespace().next()?.trim_matches('"');
+    let dir = dir.to_string_lossy();
+    let dir = if dir.is_empty() { "." } else { &dir };
+    let program = ["${CLAUDE_PLUGIN_ROOT}", "${PLUGIN_DIR}", "$PLUGIN_DIR"]
+        .iter()
+        .fold(program.to_string(), |program, var| program.replace(var, dir));
+    program.contains('/').then(|| std::path::PathBuf::from(program))
+}
+
+/// Semantic problems in a manifest that parsed: blank fields, no hooks, and
+/// hook commands whose script isn't on disk
+fn plugin_manifest_problems(manifest: &PluginManifest, dir: &std::path::Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    if manifest.name.trim().is_empty() {
+        problems.push("'name' is empty".to_string());
+    }
+    if manifest.version.trim().is_empty() {
+        problems.push("'version' is empty".to_string());
+    }
+    if manifest.hooks.is_empty() {
+        problems.push("no hooks declared".to_string());
+    }
+    for (event, matchers) in &manifest.hooks {
+        for hook in matchers.iter().flat_map(|m| &m.hooks) {
+            match (hook.kind.as_str(), &hook.command) {
+                ("command", Some(command)) => {
+                    if let Some(script) = hook_entry_point(command, dir).filter(|script| !script.exists()) {
+                        problems.push(format!("{}: entry point {} does not exist", event, script.display()));
+                    }
+                }
+                ("command", None) => problems.push(format!("{}: command hook without a 'command'", event)),
+                (kind, _) => problems.push(format!("{}: unknown hook type '{}'", event, kind)),
+            }
+        }
+    }
+    problems
+}
+
+fn show_plugin_manifest(manifest: &PluginManifest, problems: &[String]) {
+    println!("\n{}", "Plugin Manifest:".yellow());
+    println!("  Name: {} {}", manifest.name, manifest.version);
+    if let Some(description) = &manifest.description {
+        println!("  Description: {}", description);
+    }
+    for (event, matchers) in &manifest.hooks {
+        let matchers: Vec<&str> = matchers.iter().map(|m| m.matcher.as_deref().unwrap_or("*")).collect();
+        println!("  {}: {}", event, matchers.join(", "));
+    }
+    for problem in problems {
+        println!("  {} {}", "❌".red(), problem.red());
+    }
+}
+
+fn show_status(hook_opts: &HookOptions) {
+    println!("{}", "=== Hook System Status ===".cyan().bold());
+
+    // Check if config exists
+    let config_path = &hook_opts.config_dir.join("config.json");
+    let plugin_path = &hook_opts.config_dir.join("plugin.json");
+
+    let config = config_path.exists().then(|| load_config(config_path, &hook_opts.config_overrides));
+    let manifest = plugin_path.exists().then(|| load_plugin_manifest(plugin_path));
+
+    println!("\n{}", "Configuration Files:".yellow());
+    if !hook_opts.config_dir.as_os_str().is_empty() {
+        println!("  Directory: {}", hook_opts.config_dir.display());
+    }
+    match &config {
+        None => println!("  config.json: {}", "❌ Missing".red()),
+        Some(Ok(_)) => println!("  config.json: {}", "✅ Found".green()),
+        Some(Err(e)) => println!("  config.json: {}", format!("❌ present but invalid ({})", e).red()),
+    }
+    match &manifest {
+        None => println!("  plugin.json: {}", "❌ Missing".red()),
+        Some(Ok(_)) => println!("  plugin.json: {}", "✅ Found".green()),
+        Some(Err(e)) => println!("  plugin.json: {}", format!("❌ present but invalid ({})", e).red()),
+    }
+
+    if let Some(Ok(manifest)) = &manifest {
+        show_plugin_manifest(manifest, &plugin_manifest_problems(manifest, &hook_opts.config_dir));
+    }
+
+    if let Some(Ok(config)) = &config {
+        println!("\n{}", "Enabled Adapters:".yellow());
+        if config.enabled_adapters.is_empty() {
+            println!("  {}", "(none)".red());
+        }
+        for adapter in &config.enabled_adapters {
+            println!("  - {}", adapter.green());
+        }
+
+        println!("\n{}", "Settings:".yellow());
+        if let Some(timeout) = config.timeout_seconds {
+            println!("  Timeout: {}s", timeout);
+        }
+        if let Some(parallel) = config.parallel_execution {
+            println!("  Parallel execution: {}", parallel);
+        }
+        if let Some(map) = &config.decision_map {
+            println!("  Decision map: {} entries", map.len());
+        }
+    }
+
+    println!("\n{}", "Hook Runtime:".yellow());
+    match hook_opts.python.get() {
+        Ok(python) => match python_version(python) {
+            Ok(version) => println!("  Python: {}", format!("✅ {} {}", python, version).green()),
+            Err(e) => println!("  Python: {}", format!("❌ {} ({})", python, e).red()),
+        },
+        Err(e) => println!("  Python: {}", format!("❌ {}", e).red()),
+    }
+    println!(
+        "  Hook script: {}",
+        if hook_opts.script.exists() {
+            "✅ Found".green()
+        } else {
+            format!("❌ Missing ({})", hook_opts.script.display()).red()
+        }
+    );
+
+    if plugin_path.exists() {
+        show_plugin_capabilities(plugin_path, hook_opts);
+    }
+}
+
+/// The same checks as show_status, as one JSON object for scripts
+fn status_json(hook_opts: &HookOptions) -> serde_json::Value {
+    let config_path = &hook_opts.config_dir.join("config.json");
+    let plugin_path = &hook_opts.config_dir.join("plugin.json");
+
+    let config = config_path.exists().then(|| load_config(config_path, &hook_opts.config_overrides));
+    let config_json = serde_json::json!({
+        "present": config.is_some(),
+        "valid": matches!(config, Some(Ok(_))),
+        "error": match &config {
+            Some(Err(e)) => Some(e.as_str()),
+            _ => None,
+        },
+    });
+    let enabled_adapters = match &config {
+        Some(Ok(config)) => serde_json::json!(config.enabled_adapters),
+        _ => serde_json::Value::Null,
+    };
+
+    // Only ask the hook for capabilities when there are declared events to check
+    let plugin_json = if plugin_path.exists() {
+        let declared = declared_hook_events(plugin_path);
+        let supported = if declared.is_empty() { Ok(Vec::new()) } else { query_hook_capabilities(hook_opts) };
+        let events: serde_json::Map<String, serde_json::Value> = declared
+            .iter()
+            .map(|event| {
+                let state = supported.as_ref().ok().map(|events| events.contains(event));
+                (event.clone(), serde_json::json!(state))
+            })
+            .collect();
+        let manifest = load_plugin_manifest(plugin_path);
+        let problems = match &manifest {
+            Ok(manifest) => plugin_manifest_problems(manifest, &hook_opts.config_dir),
+            Err(e) => vec![e.clone()],
+        };
+        // The probe is bounded by PROBE_TIMEOUT, so a hook that hangs shows up here as unavailable
+        let capabilities = match (&supported, declared.is_empty()) {
+            (_, true) => "not_queried",
+            (Ok(_), false) => "available",
+            (Err(_), false) => "unavailable",
+        };
+        serde_json::json!({
+            "present": true,
+            "valid": problems.is_empty(),
+            "problems": problems,
+            "events": events,
+            "capabilities": capabilities,
+            "capabilities_error": supported.err(),
+        })
+    } else {
+        serde_json::json!({ "present": false })
+    };
+
+    let python = match hook_opts.python.get() {
+        Ok(python) => match python_version(python) {
+            Ok(version) => serde_json::json!({
+                "available": true,
+                "command": python.to_string(),
+                "version": version
+            }),
+            Err(e) => serde_json::json!({ "available": false, "command": python.to_string(), "error": e }),
+        },
+        Err(e) => serde_json::json!({ "available": false, "error": e }),
+    };
+
+    serde_json::json!({
+        "config_json": config_json,
+        "plugin_json": plugin_json,
+        "python": python,
+        "hook_script": {
+            "present": hook_opts.script.exists(),
+            "path": hook_opts.script.display().to_string(),
+        },
+        "enabled_adapters": enabled_adapters,
+    })
+}
+
+/// Runs `<python> --version` and returns just the version number
+fn python_version(python: &PythonCommand) -> Result<String, String> {
+    let output = python
+        .command()
+        .arg("--version")
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("exited with {}", output.status));
+    }
+
+    // Python 2 prints the version to stderr
+    let text = if output.stdout.is_empty() { &output.stderr } else { &output.stdout };
+    let text = String::from_utf8_lossy(text);
+    Ok(text.trim().trim_start_matches("Python").trim().to_string())
+}
+
+/// Cross-checks the hook events declared in plugin.json against what the orchestrator reports
+fn show_plugin_capabilities(plugin_path: &std::path::Path, hook_opts: &HookOptions) {
+    let declared = declared_hook_events(plugin_path);
+
+    if declared.is_empty() {
+        return;
+    }
+
+    println!("\n{}", "Plugin capabilities:".yellow());
+
+    let supported = query_hook_capabilities(hook_opts);
+    if let Err(e) = &supported {
+        println!("  {} {}", "Capability query failed:".red(), e);
+    }
+
+    for event in &declared {
+        let state = match &supported {
+            Ok(events) if events.contains(event) => "✅ supported".green(),
+            Ok(_) => "❌ unsupported".red(),
+            Err(_) => "❔ unknown".yellow(),
+        };
+        println!("  {}: {}", event, state);
+    }
+}
+
+/// Hook event names declared under `hooks` in plugin.json; empty when unreadable
+fn declared_hook_events(plugin_path: &std::path::Path) -> Vec<String> {
+    std::fs::read_to_string(plugin_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|plugin| plugin.get("hooks").and_then(|h| h.as_object()).map(|h| h.keys().cloned().collect()))
+        .unwrap_or_default()
+}
+
+/// Longest a probe command may take. An orchestrator that doesn't know the
+/// command runs a whole review instead, and status shouldn't wait on that.
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Sends the hook a `{"command": ...}` probe, bounded by PROBE_TIMEOUT (or a
+/// shorter --timeout), and returns its JSON response
+fn probe_hook(command: &str, hook_opts: &HookOptions) -> Result<serde_json::Value, String> {
+    let timeout = hook_opts.timeout.map_or(PROBE_TIMEOUT, |timeout| timeout.min(PROBE_TIMEOUT));
+    let probe_opts = HookOptions { timeout: Some(timeout), ..hook_opts.clone() };
+    let output = spawn_hook(&serde_json::json!({ "command": command }), &probe_opts).map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("Hook failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("Invalid JSON response: {}", e))
+}
+
+/// Asks the orchestrator which hook events it handles
+fn query_hook_capabilities(hook_opts: &HookOptions) -> Result<Vec<String>, String> {
+    let response = probe_hook("capabilities", hook_opts)?;
+    response
+        .get("events")
+        .and_then(|e| e.as_array())
+        .map(|events| events.iter().filter_map(|e| e.as_str().map(String::from)).collect())
+        .ok_or_else(|| "orchestrator does not support the capabilities command".to_string())
+}
+
+/// An adapter the orchestrator reports; `available` is None when it doesn't say
+struct AdapterInfo {
+    name: String,
+    available: Option<bool>,
+}
+
+/// Asks the orchestrator which adapters it supports. Entries may be plain names
+/// or `{"name": ..., "available": ...}` objects.
+fn query_adapters(hook_opts: &HookOptions) -> Result<Vec<AdapterInfo>, String> {
+    let response = probe_hook("list_adapters", hook_opts)?;
+    let adapters = response
+        .get("adapters")
+        .and_then(|a| a.as_array())
+        .ok_or_else(|| "orchestrator does not support the list_adapters command".to_string())?;
+    Ok(adapters
+        .iter()
+        .filter_map(|entry| match entry {
+            serde_json::Value::String(name) => Some(AdapterInfo { name: name.clone(), available: None }),
+            entry => Some(AdapterInfo {
+                name: entry.get("name")?.as_str()?.to_string(),
+                available: entry.get("available").and_then(|a| a.as_bool()),
+            }),
+        })
+        .collect())
+}
+
+fn list_adapters(hook_opts: &HookOptions) {
+    println!("{}", "=== Adapters ===".cyan().bold());
+
+    let enabled = match load_config(&hook_opts.config_dir.join("config.json"), &hook_opts.config_overrides) {
+        Ok(config) => config.enabled_adapters,
+        Err(e) => {
+            println!("\n{} config.json: {}", "Warning:".yellow(), e);
+            Vec::new()
+        }
+    };
+
+    match query_adapters(hook_opts) {
+        Ok(adapters) => {
+            let names = adapters.iter().map(|a| a.name.as_str()).chain(enabled.iter().map(String::as_str));
+            let width = names.map(str::len).max().unwrap_or(0);
+            println!();
+            for adapter in &adapters {
+                let state = if enabled.contains(&adapter.name) {
+                    "✅ enabled".green()
+                } else {
+                    "⬜ disabled".dimmed()
+                };
+                let availability = match adapter.available {
+                    Some(false) => format!("  {}", "(not available)".yellow()),
+                    _ => String::new(),
+                };
+                println!("  {:<width$}  {}{}", adapter.name, state, availability);
+            }
+            for name in enabled.iter().filter(|name| !adapters.iter().any(|a| &a.name == *name)) {
+                println!("  {:<width$}  {}", name, "❌ enabled but not supported by the hook".red());
+            }
+        }
+        Err(e) => {
+            println!("\n{} {}", "Note:".yellow(), e);
+            println!("Showing the adapters enabled in config.json instead:");
+            if enabled.is_empty() {
+                println!("  {}", "(none)".red());
+            }
+            for name in &enabled {
+                println!("  - {}", name.green());
+            }
+        }
+    }
+}
+
+fn generate_test_files(args: &GenerateArgs, seed: Option<u64>) {
+    let GenerateArgs { ref issue_type, lang, count, ref out_dir } = *args;
+    if count == 1 {
+        println!("{}", format!("=== Generating Test File ({}) ===", issue_type).cyan().bold());
+    } else {
+        println!("{}", format!("=== Generating {} Test Files ({}) ===", count, issue_type).cyan().bold());
+    }
+
+    if let Err(e) = std::fs::create_dir_all(out_dir) {
+        eprintln!("{} Failed to create {}: {}", "Error:".red().bold(), out_dir.display(), e);
+        std::process::exit(1);
+    }
+
+    let base = test_file_content(issue_type, lang);
+    let seed = seed.unwrap_or_else(random_seed);
+    let mut rng = SplitMix64(seed);
+    for n in 1..=count {
+        let (filename, content) = if count == 1 {
+            (format!("test_{}.{}", issue_type, lang.extension()), base.to_string())
+        } else {
+            (
+                format!("test_{}_{}.{}", issue_type, n, lang.extension()),
+                vary_test_content(base, n, count, &mut rng),
+            )
+        };
+        let path = out_dir.join(filename);
+        if let Err(e) = std::fs::write(&path, &content) {
+            eprintln!("{} Failed to write {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+
+        println!("\n{} {}", "Created:".green(), path.display());
+        if count == 1 {
+            println!("\n{}", "File Content:".yellow());
+            println!("{}", content);
+        }
+    }
+
+    if count > 1 {
+        println!("\n{} {} files written to {}", "Summary:".green().bold(), count, out_dir.display());
+        println!("{}", format!("Reproduce with --seed {}", seed).dimmed());
+    }
+    println!("\n{}", "Now use 'hook-test simulate -t Edit -f <file>' to test the hook".cyan());
+}
+
+/// Words appended to function names in generated variants
+const VARIANT_SUFFIXES: [&str; 8] = ["user", "order", "item", "record", "batch", "cache", "report", "entry"];
+
+/// Renames the snippet's functions (except entry points) with suffixes drawn from
+/// `rng`, in the casing each name already uses, and tags the header comment with
+/// the variant number, so the files in a corpus differ in their code
+fn vary_test_content(base: &str, n: u32, count: u32, rng: &mut SplitMix64) -> String {
+    let (header, rest) = base.split_once('\n').unwrap_or((base, ""));
+    let mut body = rest.to_string();
+    for name in defined_functions(rest) {
+        let suffix = rng.pick(&VARIANT_SUFFIXES);
+        let renamed = if name.chars().any(|c| c.is_ascii_uppercase()) {
+            format!("{}{}{}", name, suffix[..1].to_ascii_uppercase(), &suffix[1..])
+        } else {
+            format!("{}_{}", name, suffix)
+        };
+        body = replace_identifier(&body, name, &renamed);
+    }
+    format!("{} (variant {} of {})\n{}", header, n, count, body)
+}
+
+/// Names of the functions a generated snippet defines, skipping `main` and dunder methods
+fn defined_functions(code: &str) -> Vec<&str> {
+    const KEYWORDS: [&str; 6] = ["pub fn ", "fn ", "async def ", "def ", "async function ", "function "];
+    let mut names = Vec::new();
+    for line in code.lines() {
+        let line = line.trim_start();
+        let Some(rest) = KEYWORDS.iter().find_map(|keyword| line.strip_prefix(keyword)) else {
+            continue;
+        };
+        let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let name = &rest[..end];
+        if !name.is_empty() && name != "main" && !name.starts_with("__") && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Replaces whole-word occurrences of `name`, leaving longer identifiers that contain it alone
+fn replace_identifier(code: &str, name: &str, replacement: &str) -> String {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(i) = rest.find(name) {
+        let (before, after) = (&rest[..i], &rest[i + name.len()..]);
+        let whole = !before.ends_with(is_ident) && !after.starts_with(is_ident);
+        out.push_str(before);
+        out.push_str(if whole { replacement } else { name });
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+fn test_file_content(issue_type: &str, lang: Lang) -> &'static str {
+    match lang {
+        Lang::Rust => rust_test_content(issue_type),
+        Lang::Python => python_test_content(issue_type),
+        Lang::Javascript => javascript_test_content(issue_type),
+    }
+}
+
+fn rust_test_content(issue_type: &str) -> &'static str {
+    match issue_type {
+        "bug" => r#"// Test file with intentional bug
+fn calculate_average(numbers: &[i32]) -> i32 {
+    let sum: i32 = numbers.iter().sum();
+    sum / numbers.len() as i32  // BUG: Division by zero if empty
+}
+
+fn main() {
+    let empty: Vec<i32> = vec![];
+    println!("Average: {}", calculate_average(&empty));
+}
+"#,
+        "security" => r#"// Test file with security issue
+use std::process::Command;
+
+fn run_user_command(input: &str) {
+    // SECURITY: Command injection vulnerability
+    Command::new("sh")
+        .arg("-c")
+        .arg(input)  // Unsanitized user input!
+        .spawn()
+        .expect("Failed to execute");
+}
+
+fn main() {
+    run_user_command("echo hello; rm -rf /");
+}
+"#,
+        "style" => r#"// Test file with style issues
+fn BadFunctionName() {  // Should be snake_case
+    let X = 5;  // Should be lowercase
+    let unused_var = 10;  // Unused variable
+    println!("{}",X);  // Missing space
+}
+"#,
+        "performance" => r#"// Test file with performance issues
+fn unique_ids(ids: &[u64]) -> Vec<u64> {
+    let mut seen: Vec<u64> = Vec::new();
+    for id in ids {
+        // PERFORMANCE: O(n^2) - Vec::contains scans the whole vector every time
+        if !seen.contains(id) {
+            seen.push(*id);
+        }
+    }
+    seen
+}
+
+fn total_len(lines: &[String]) -> usize {
+    let mut total = 0;
+    for line in lines {
+        let owned = line.clone();  // PERFORMANCE: needless clone in a hot loop
+        total += owned.len();
+    }
+    total
+}
+
+fn main() {
+    let ids: Vec<u64> = (0..100_000).map(|i| i % 1000).collect();
+    println!("{}", unique_ids(&ids).len());
+    println!("{}", total_len(&vec!["line".to_string(); 100_000]));
+}
+"#,
+        "concurrency" => r#"// Test file with concurrency issues
+use std::sync::Mutex;
+use std::thread;
+
+static mut COUNTER: u64 = 0;
+
+fn increment() {
+    // CONCURRENCY: data race - unsynchronized &mut access to shared state
+    unsafe {
+        let counter = &mut *std::ptr::addr_of_mut!(COUNTER);
+        *counter += 1;
+    }
+}
+
+fn transfer(balance: &Mutex<i64>, amount: i64) {
+    let mut guard = balance.lock().unwrap();
+    if *guard >= amount {
+        // CONCURRENCY: locking the same Mutex twice deadlocks
+        let current = *balance.lock().unwrap();
+        *guard = current - amount;
+    }
+}
+
+fn main() {
+    let handles: Vec<_> = (0..8).map(|_| thread::spawn(increment)).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    transfer(&Mutex::new(100), 10);
+}
+"#,
+        _ => "// Unknown issue type\nfn main() {}\n",
+    }
+}
+
+fn python_test_content(issue_type: &str) -> &'static str {
+    match issue_type {
+        "bug" => r#"# Test file with intentional bug
+def calculate_average(numbers):
+    return sum(numbers) / len(numbers)  # BUG: ZeroDivisionError if empty
+
+
+if __name__ == "__main__":
+    print("Average:", calculate_average([]))
+"#,
+        "security" => r#"# Test file with security issue
+import subprocess
+
+
+def run_user_command(user_input):
+    # SECURITY: Command injection vulnerability
+    subprocess.run(user_input, shell=True)  # Unsanitized user input!
+
+
+if __name__ == "__main__":
+    run_user_command("echo hello; rm -rf /")
+"#,
+        "style" => r#"# Test file with style issues
+def BadFunctionName():  # Should be snake_case
+    X = 5  # Should be lowercase
+    unused_var = 10  # Unused variable
+    print(X,end = "")  # Inconsistent spacing
+"#,
+        "performance" => r#"# Test file with performance issues
+def unique_ids(ids):
+    seen = []
+    for i in ids:
+        # PERFORMANCE: O(n^2) - list membership scans the whole list every time
+        if i not in seen:
+            seen.append(i)
+    return seen
+
+
+def join_lines(lines):
+    result = ""
+    for line in lines:
+        result += line + "\n"  # PERFORMANCE: quadratic string concatenation
+    return result
+
+
+if __name__ == "__main__":
+    print(len(unique_ids([i % 1000 for i in range(100_000)])))
+    print(len(join_lines(["line"] * 100_000)))
+"#,
+        "concurrency" => r#"# Test file with concurrency issues
+import threading
+
+counter = 0
+lock = threading.Lock()
+
+
+def increment():
+    global counter
+    # CONCURRENCY: race condition - read-modify-write without the lock
+    for _ in range(100_000):
+        counter += 1
+
+
+def transfer(balance, amount):
+    with lock:
+        if balance["value"] >= amount:
+            # CONCURRENCY: acquiring a non-reentrant Lock twice deadlocks
+            with lock:
+                balance["value"] -= amount
+
+
+if __name__ == "__main__":
+    threads = [threading.Thread(target=increment) for _ in range(8)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    transfer({"value": 100}, 10)
+"#,
+        _ => "# Unknown issue type\n",
+    }
+}
+
+fn javascript_test_content(issue_type: &str) -> &'static str {
+    match issue_type {
+        "bug" => r#"// Test file with intentional bug
+function calculateAverage(numbers) {
+  const sum = numbers.reduce((a, b) => a + b, 0);
+  return sum / numbers.length; // BUG: NaN if empty
+}
+
+console.log("Average:", calculateAverage([]));
+"#,
+        "security" => r#"// Test file with security issue
+const { exec } = require("child_process");
+
+function runUserCommand(input) {
+  // SECURITY: Command injection vulnerability
+  exec(input); // Unsanitized user input!
+}
+
+runUserCommand("echo hello; rm -rf /");
+"#,
+        "style" => r#"// Test file with style issues
+function bad_function_name() {  // Should be camelCase
+  var X = 5  // Use const/let, missing semicolon
+  var unusedVar = 10;  // Unused variable
+  console.log(X)
+}
+"#,
+        "performance" => r#"// Test file with performance issues
+function uniqueIds(ids) {
+  const seen = [];
+  for (const id of ids) {
+    // PERFORMANCE: O(n^2) - Array.includes scans the whole array every time
+    if (!seen.includes(id)) {
+      seen.push(id);
+    }
+  }
+  return seen;
+}
+
+function totalLength(items) {
+  let total = 0;
+  for (const item of items) {
+    const copy = JSON.parse(JSON.stringify(item)); // PERFORMANCE: needless deep clone in a hot loop
+    total += copy.name.length;
+  }
+  return total;
+}
+
+console.log(uniqueIds(Array.from({ length: 100000 }, (_, i) => i % 1000)).length);
+console.log(totalLength(Array(100000).fill({ name: "item" })));
+"#,
+        "concurrency" => r#"// Test file with concurrency issues
+let balance = 100;
+
+async function withdraw(amount) {
+  // CONCURRENCY: check-then-act race across an await
+  if (balance >= amount) {
+    await new Promise((resolve) => setTimeout(resolve, 10));
+    balance -= amount;
+  }
+}
+
+Promise.all([withdraw(80), withdraw(80)]).then(() => {
+  console.log("Balance:", balance); // -60
+});
+"#,
+        _ => "// Unknown issue type\n",
+    }
+}
+
+/// Returns the process exit status: 0, EXIT_BLOCKED or EXIT_HOOK_ERROR
+fn invoke_hook(args: InvokeArgs, hook_opts: &HookOptions, masker: &PathMasker) -> i32 {
+    let InvokeArgs { ref stage, strict_json, json_stream, repeat, timeout, retries, .. } = args;
+    let stage = stage.as_str();
+    let hook_opts = &HookOptions {
+        timeout: Some(std::time::Duration::from_secs(timeout)),
+        retries,
+        env: args.env.clone(),
+        stream: args.stream,
+        adapters: args.adapters.clone(),
+        ..hook_opts.clone()
+    };
+
+    let mut files = args.file.clone();
+    if let Some(list) = &args.files_from {
+        match std::fs::read_to_string(list) {
+            Ok(content) => {
+                files.extend(content.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from))
+            }
+            Err(e) => {
+                println!("{} Failed to read {}: {}", "Aborted:".red().bold(), list.display(), e);
+                return EXIT_HOOK_ERROR;
+            }
+        }
+    }
+    if files.len() > 1 || args.files_from.is_some() {
+        let single_only = args.all_stages || repeat != 1 || json_stream.is_some();
+        let single_only = single_only || args.save.is_some() || args.output_file.is_some();
+        let single_only = single_only || args.expect.is_some() || args.dry_run || args.explain;
+        if single_only || args.editor || args.stdin {
+            println!(
+                "{} several files can't be combined with --all-stages, --repeat, --json-stream, \
+                 --save, --output-file, --expect, --dry-run, --explain, --editor or --stdin",
+                "Aborted:".red().bold()
+            );
+            return EXIT_HOOK_ERROR;
+        }
+        return invoke_files(stage, &files, &args, hook_opts, masker);
+    }
+
+    let quiet = hook_opts.verbosity == Verbosity::Quiet;
+    if !quiet && json_stream.is_none() && !args.jsonl {
+        if args.all_stages {
+            println!("{}", "=== Invoking Hook (all stages) ===".cyan().bold());
+        } else {
+            println!("{}", format!("=== Invoking Hook (stage: {}) ===", stage).cyan().bold());
+        }
+    }
+
+    let hook_input = match build_invoke_input(&args).and_then(|mut input| {
+        args.overrides.apply(&mut input);
+        set_language(&mut input, args.language.as_deref());
+        if hook_opts.verbosity == Verbosity::Verbose {
+            eprintln!("{} {}", "Language:".dimmed(), describe_language(&input));
+        }
+        if args.strip_comments {
+            strip_input_comments(&mut input)?;
+        }
+        Ok(input)
+    }) {
+        Ok(input) => input,
+        Err(e) => {
+            println!("\n{} {}", "Aborted:".red().bold(), e);
+            return EXIT_HOOK_ERROR;
+        }
+    };
+
+    if args.dry_run {
+        let stages: Vec<&str> =
+            if args.all_stages { hook_opts.stages.iter().map(String::as_str).collect() } else { vec![stage] };
+        for stage in stages {
+            if let Err(e) = print_dry_run(stage, &hook_input, hook_opts, masker) {
+                println!("\n{} {}", "Error:".red().bold(), e);
+                return e.exit_code();
+            }
+        }
+        return 0;
+    }
+
+    let sinks = ResultSinks { save: args.save.as_deref(), output_file: args.output_file.as_deref() };
+    if args.all_stages {
+        return run_all_stages(&hook_input, strict_json, args.jsonl, sinks, hook_opts, masker);
+    }
+
+    let interval = std::time::Duration::from_millis(args.interval);
+    if let Some(stream) = json_stream {
+        if repeat == 1 {
+            return emit_json_result(stage, &hook_input, strict_json, stream, hook_opts, masker);
+        }
+        return run_repeated_json_stream(repeat, interval, || {
+            emit_json_result(stage, &hook_input, strict_json, stream, hook_opts, masker)
+        });
+    }
+
+    if repeat != 1 {
+        return run_repeated_invoke(stage, &hook_input, strict_json, repeat, interval, hook_opts);
+    }
+
+    let response_opts = ResponseOptions { strict_json, explain: args.explain, max_lines: args.max_lines };
+    let exit_code = invoke_once(stage, &hook_input, response_opts, sinks, hook_opts, masker);
+    match args.expect {
+        Some(expect) => check_expectation(expect, exit_code),
+        None => exit_code,
+    }
+}
+
+/// Compares invoke_once's exit status against --expect. A hook error never
+/// satisfies an expectation and keeps its own exit status.
+fn check_expectation(expect: Expectation, exit_code: i32) -> i32 {
+    let actual = match exit_code {
+        0 => Expectation::Continue.as_str(),
+        EXIT_BLOCKED => Expectation::Block.as_str(),
+        _ => "error",
+    };
+    if actual == expect.as_str() {
+        println!("\n{}", "ASSERTION PASSED".green().bold());
+        return 0;
+    }
+    println!(
+        "\n{}",
+        format!("ASSERTION FAILED: expected {}, got {}", expect.as_str(), actual).red().bold()
+    );
+    if actual == "error" { exit_code } else { EXIT_ASSERTION_FAILED }
+}
+
+/// Calls the hook once and prints its decision. Returns the exit status.
+fn invoke_once(
+    stage: &str,
+    hook_input: &serde_json::Value,
+    response_opts: ResponseOptions,
+    sinks: ResultSinks,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> i32 {
+    let ResponseOptions { strict_json, explain, max_lines } = response_opts;
+    let quiet = hook_opts.verbosity == Verbosity::Quiet;
+    if !quiet {
+        println!("\n{}", "Sending to Python hook...".yellow());
+    }
+    let start = Instant::now();
+
+    let (result, stderr) = match call_python_hook_with_stderr(stage, hook_input, hook_opts) {
+        Ok((stdout, stderr)) => (Ok(stdout), stderr),
+        Err(e) => (Err(e), String::new()),
+    };
+    let elapsed = start.elapsed();
+    sinks.record(stage, hook_input, &result, strict_json, elapsed, &hook_opts.decision_map);
+
+    // Verbose mode already echoed stderr as it came back from spawn_hook
+    if !stderr.is_empty() && hook_opts.verbosity != Verbosity::Verbose {
+        println!("\n{}", "Hook warnings:".dimmed());
+        for line in stderr.lines() {
+            println!("  {}", masker.mask(line).dimmed());
+        }
+    }
+
+    match result {
+        Ok(output) => {
+            if !quiet {
+                println!("\n{} ({:?})", "Hook Response:".green().bold(), elapsed);
+            }
+
+            match parse_hook_output(&output, strict_json) {
+                Ok(parsed) => {
+                    let (decision, warning) = parsed.resolve_decision(&hook_opts.decision_map);
+                    if let Some(warning) = warning {
+                        println!("  {} {}", "Warning:".yellow(), warning);
+                    }
+                    println!("  Decision: {}", decision.label());
+
+                    let msg = parsed.system_message.as_deref();
+                    match msg.and_then(parse_review_message).filter(|_| !decision.allows_continue()) {
+                        Some(reason) => print_block_reason(&reason, max_lines, masker),
+                        None => {
+                            if let Some(msg) = msg {
+                                println!("\n{}", "System Message:".yellow());
+                                print_capped_lines(msg, "  ", max_lines, masker);
+                            }
+                        }
+                    }
+                    if let Some(issues) = &parsed.issues {
+                        print_issues(issues, masker);
+                    }
+                    if explain && !decision.allows_continue() {
+                        print_explanation(stage, hook_input, hook_opts, masker);
+                    }
+
+                    if decision.allows_continue() { 0 } else { EXIT_BLOCKED }
+                }
+                Err(e) if strict_json => {
+                    println!("  {} {}", "Strict JSON check failed:".red().bold(), e);
+                    println!("  Raw output: {}", masker.mask(&output));
+                    EXIT_HOOK_ERROR
+                }
+                Err(e) => {
+                    println!("  {} {}", "Invalid hook output:".red().bold(), e);
+                    println!("  Raw output: {}", masker.mask(&output));
+                    EXIT_HOOK_ERROR
+                }
+            }
+        }
+        Err(e) => {
+            println!("\n{} {} ({:?})", "Error:".red().bold(), masker.mask(&e.to_string()), elapsed);
+            e.exit_code()
+        }
+    }
+}
+
+/// A system message in the orchestrator's `[자기검열-<stage>] <emoji> <summary>` format
+struct ReviewMessage<'a> {
+    /// Stage from the tag; None for the bare `[자기검열]` tag
+    stage: Option<&'a str>,
+    status: &'a str,
+    summary: &'a str,
+    /// Everything after the first line (adapter feedback), trimmed
+    body: &'a str,
+}
+
+const REVIEW_TAG: &str = "[자기검열";
+
+fn parse_review_message(msg: &str) -> Option<ReviewMessage<'_>> {
+    let (first, body) = msg.trim_start().split_once('\n').unwrap_or((msg.trim_start(), ""));
+    let rest = first.strip_prefix(REVIEW_TAG)?;
+    let (tag, rest) = rest.split_once(']')?;
+    let stage = match tag {
+        "" => None,
+        tag => Some(tag.strip_prefix('-')?),
+    };
+    let (status, summary) = rest.trim().split_once(char::is_whitespace).unwrap_or((rest.trim(), ""));
+    if status.is_empty() {
+        return None;
+    }
+    Some(ReviewMessage { stage, status, summary: summary.trim(), body: body.trim() })
+}
+
+fn print_block_reason(reason: &ReviewMessage, max_lines: Option<usize>, masker: &PathMasker) {
+    println!("\n{}", "Block Reason:".yellow());
+    if let Some(stage) = reason.stage {
+        println!("  Stage: {}", stage);
+    }
+    println!("  Status: {}", reason.status);
+    if !reason.summary.is_empty() {
+        println!("  Summary: {}", masker.mask(reason.summary));
+    }
+    if !reason.body.is_empty() {
+        println!("  Details:");
+        print_capped_lines(reason.body, "    ", max_lines, masker);
+    }
+}
+
+/// Prints up to `max_lines` lines of `text` (all for None or 0), then a footer
+/// saying how many were left out
+fn print_capped_lines(text: &str, indent: &str, max_lines: Option<usize>, masker: &PathMasker) {
+    let total = text.lines().count();
+    let shown = max_lines.filter(|&n| n > 0).unwrap_or(total).min(total);
+    for line in text.lines().take(shown) {
+        println!("{}{}", indent, masker.mask(line));
+    }
+    if shown < total {
+        let footer = format!("… ({} more lines, use --max-lines 0 for all)", total - shown);
+        println!("{}{}", indent, footer.dimmed());
+    }
+}
+
+/// Asks the hook why it blocked with an `explain` request (the usual wrapper plus
+/// `"command": "explain"`) and returns its `explanation`
+fn query_explanation(stage: &str, input: &serde_json::Value, hook_opts: &HookOptions) -> Result<String, String> {
+    let mut request = hook_wrapper(stage, input, hook_opts);
+    request["command"] = serde_json::json!("explain");
+    let output = spawn_hook(&request, hook_opts).map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("Hook failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let response: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Invalid JSON response: {}", e))?;
+    match response.get("explanation") {
+        Some(serde_json::Value::String(text)) => Ok(text.clone()),
+        Some(serde_json::Value::Array(lines)) => {
+            Ok(lines.iter().filter_map(|line| line.as_str()).collect::<Vec<_>>().join("\n"))
+        }
+        _ => Err("hook does not support the explain command".to_string()),
+    }
+}
+
+fn print_explanation(stage: &str, input: &serde_json::Value, hook_opts: &HookOptions, masker: &PathMasker) {
+    println!("\n{}", "Explanation:".yellow());
+    match query_explanation(stage, input, hook_opts) {
+        Ok(explanation) => {
+            for line in explanation.lines() {
+                println!("  {}", masker.mask(line));
+            }
+        }
+        Err(e) => println!("  {}", format!("{}; the message above is all the hook gave", e).dimmed()),
+    }
+}
+
+/// Prints issues most severe first, then by line
+fn print_issues(issues: &[Issue], masker: &PathMasker) {
+    if issues.is_empty() {
+        return;
+    }
+    let mut sorted: Vec<&Issue> = issues.iter().collect();
+    sorted.sort_by_key(|issue| (issue.severity_rank(), issue.line.unwrap_or(u32::MAX)));
+
+    println!("\n{}", format!("Issues ({}):", issues.len()).yellow());
+    for issue in sorted {
+        let severity = match issue.severity_rank() {
+            0 => issue.severity.red().bold(),
+            1 => issue.severity.yellow(),
+            _ => issue.severity.dimmed(),
+        };
+        let line = issue.line.map(|l| format!("line {}: ", l)).unwrap_or_default();
+        let category = issue.category.as_deref().map(|c| format!(" [{}]", c)).unwrap_or_default();
+        println!("  {}{} {}{}", severity, category, line, masker.mask(&issue.message));
+    }
+}
+
+/// One row of the multi-file Invoke summary
+struct FileOutcome {
+    decision: Result<Decision, String>,
+    elapsed: std::time::Duration,
+}
+
+/// Reviews each file as its own Edit across `jobs` worker threads and prints
+/// a summary in the order the files were given. Returns the worst exit status.
+fn invoke_files(
+    stage: &str,
+    files: &[String],
+    args: &InvokeArgs,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> i32 {
+    let InvokeArgs { strict_json, jobs, .. } = *args;
+    if hook_opts.verbosity != Verbosity::Quiet {
+        println!(
+            "{}",
+            format!("=== Invoking Hook (stage: {}, {} files, {} jobs) ===", stage, files.len(), jobs).cyan().bold()
+        );
+    }
+
+    let outcomes: std::sync::Mutex<Vec<Option<FileOutcome>>> =
+        std::sync::Mutex::new(files.iter().map(|_| None).collect());
+
+    // Worker t takes files t, t+jobs, ...; results land in their input slot
+    std::thread::scope(|scope| {
+        for t in 0..(jobs as usize).min(files.len()) {
+            let outcomes = &outcomes;
+            scope.spawn(move || {
+                for i in (t..files.len()).step_by(jobs as usize) {
+                    let start = Instant::now();
+                    let decision = std::fs::read_to_string(&files[i])
+                        .map_err(|e| format!("Failed to read {}: {}", files[i], e))
+                        .and_then(|content| {
+                            let session_id = format!("invoke-{}-{}", chrono::Utc::now().timestamp(), i + 1);
+                            let mut input = edit_hook_input(&session_id, &files[i], "", &content);
+                            args.overrides.apply(&mut input);
+                            set_language(&mut input, args.language.as_deref());
+                            if args.strip_comments {
+                                strip_input_comments(&mut input)?;
+                            }
+                            call_python_hook(stage, &input, hook_opts)
+                                .and_then(|output| {
+                                    parse_hook_output(&output, strict_json).map_err(HookError::InvalidOutput)
+                                })
+                                .map(|parsed| parsed.resolve_decision(&hook_opts.decision_map).0)
+                                .map_err(|e| e.to_string())
+                        });
+                    let outcome = FileOutcome { decision, elapsed: start.elapsed() };
+                    outcomes.lock().unwrap()[i] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let width = files.iter().map(|f| masker.mask(f).chars().count()).max().unwrap_or(0);
+    let mut exit_code = 0;
+    println!("\n{}", "Summary:".green().bold());
+    for (file, outcome) in files.iter().zip(outcomes.into_inner().unwrap()) {
+        let outcome = outcome.expect("every file is assigned to a worker");
+        let file = masker.mask(file);
+        match outcome.decision {
+            Ok(decision) => {
+                if !decision.allows_continue() {
+                    exit_code = exit_code.max(EXIT_BLOCKED);
+                }
+                println!("  {:<width$}  {:>10.2?}  {}", file, outcome.elapsed, decision.label(), width = width);
+            }
+            Err(e) => {
+                exit_code = EXIT_HOOK_ERROR;
+                println!(
+                    "  {:<width$}  {:>10.2?}  {} {}",
+                    file,
+                    outcome.elapsed,
+                    "💥 ERROR".red(),
+                    masker.mask(&e),
+                    width = width
+                );
+            }
+        }
+    }
+
+    exit_code
+}
+
+/// Directories Watch never descends into, whatever .hookignore says
+const WATCH_ALWAYS_IGNORED: &[&str] = &[".git"];
+
+/// Globs from `.hookignore` in the config directory, one per line; blank lines and `#` comments are skipped
+fn load_hookignore(config_dir: &std::path::Path) -> Vec<String> {
+    std::fs::read_to_string(config_dir.join(".hookignore"))
+        .map(|content| {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Matches `*` and `?` within one path segment and `**` across segments
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            ['*', '*', '/', rest @ ..] => {
+                (0..=text.len()).any(|i| (i == 0 || text[i - 1] == '/') && matches(rest, &text[i..]))
+            }
+            ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            ['*', rest @ ..] => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| matches(rest, &text[i..])),
+            ['?', rest @ ..] => matches!(text.first(), Some(&c) if c != '/') && matches(rest, &text[1..]),
+            [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Whether a path relative to the watched directory is ignored. Like .gitignore,
+/// a glob without `/` is matched against each file or directory name and one
+/// with `/` against the leading part of the path, so an ignored directory
+/// ignores everything under it.
+fn is_ignored(relative: &std::path::Path, ignore: &[String]) -> bool {
+    let components: Vec<String> =
+        relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    let always = WATCH_ALWAYS_IGNORED.iter().copied();
+    always.chain(ignore.iter().map(String::as_str)).any(|pattern| {
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.contains('/') {
+            let pattern = pattern.trim_start_matches('/');
+            (1..=components.len()).any(|n| glob_match(pattern, &components[..n].join("/")))
+        } else {
+            components.iter().any(|component| glob_match(pattern, component))
+        }
+    })
+}
+
+/// Modification times of the watched files: just `root` when it's a file,
+/// otherwise every file under it that isn't ignored
+fn watched_files(
+    root: &std::path::Path,
+    ignore: &[String],
+) -> std::collections::BTreeMap<std::path::PathBuf, std::time::SystemTime> {
+    let mut files = std::collections::BTreeMap::new();
+    if !root.is_dir() {
+        if let Ok(modified) = std::fs::metadata(root).and_then(|m| m.modified()) {
+            files.insert(root.to_path_buf(), modified);
+        }
+        return files;
+    }
+
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if is_ignored(path.strip_prefix(root).unwrap_or(&path), ignore) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else if let Ok(modified) = metadata.modified() {
+                files.insert(path, modified);
+            }
+        }
+    }
+    files
+}
+
+/// Polls until some watched file is added or modified and then stops changing
+/// for one `debounce` window, so one save triggers one run. Returns those files.
+fn wait_for_changes(
+    root: &std::path::Path,
+    ignore: &[String],
+    debounce: std::time::Duration,
+    seen: &mut std::collections::BTreeMap<std::path::PathBuf, std::time::SystemTime>,
+) -> Vec<std::path::PathBuf> {
+    loop {
+        std::thread::sleep(debounce);
+        let mut settled = watched_files(root, ignore);
+        if settled == *seen {
+            continue;
+        }
+        loop {
+            std::thread::sleep(debounce);
+            let now = watched_files(root, ignore);
+            if now == settled {
+                break;
+            }
+            settled = now;
+        }
+
+        let changed: Vec<std::path::PathBuf> = settled
+            .iter()
+            .filter(|(path, modified)| seen.get(*path) != Some(*modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        *seen = settled;
+        // Deletions alone have nothing to review
+        if !changed.is_empty() {
+            return changed;
+        }
+    }
+}
+
+fn watch_path(
+    path: &std::path::Path,
+    stage: &str,
+    debounce: std::time::Duration,
+    ignore: &[String],
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) {
+    let watching_dir = path.is_dir();
+    let mut seen = watched_files(path, ignore);
+    // A single file is reviewed right away; a directory waits for its first change
+    let mut changed = if watching_dir { Vec::new() } else { vec![path.to_path_buf()] };
+    let mut previous: HashMap<std::path::PathBuf, String> = HashMap::new();
+
+    if watching_dir {
+        println!(
+            "{}",
+            format!("=== Watching {} (stage: {}) ===", masker.mask(&path.display().to_string()), stage)
+                .cyan()
+                .bold()
+        );
+        println!("{} files, debounce {} ms", seen.len(), debounce.as_millis());
+    }
+
+    loop {
+        if !changed.is_empty() {
+            // Clear the screen so each run starts from the top
+            print!("\x1B[2J\x1B[H");
+            println!(
+                "{}",
+                format!("=== Watching {} (stage: {}) ===", masker.mask(&path.display().to_string()), stage)
+                    .cyan()
+                    .bold()
+            );
+            println!(
+                "{}",
+                format!("{}  debounce {} ms", chrono::Local::now().format("%H:%M:%S"), debounce.as_millis()).dimmed()
+            );
+        }
+
+        for file in &changed {
+            let contents = match std::fs::read_to_string(file) {
+                Ok(contents) => contents,
+                Err(e) if watching_dir => {
+                    println!("\n{} {}: {}", "Skipping".yellow(), masker.mask(&file.display().to_string()), e);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), file.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            println!("\n{} {}", "Triggered by".yellow(), masker.mask(&file.display().to_string()));
+
+            // Send the previous save as old_string so the hook sees what changed
+            let old = previous.get(file).cloned().unwrap_or_default();
+            let session_id = format!("watch-{}", chrono::Utc::now().timestamp());
+            let hook_input = edit_hook_input(&session_id, &file.display().to_string(), &old, &contents);
+            invoke_once(stage, &hook_input, ResponseOptions::default(), ResultSinks::default(), hook_opts, masker);
+            previous.insert(file.clone(), contents);
+        }
+
+        println!("\n{}", "Waiting for changes (Ctrl-C to stop)...".dimmed());
+        changed = wait_for_changes(path, ignore, debounce, &mut seen);
+    }
+}
+
+/// Runs every stage in order, halting at the first block or error.
+/// Returns the exit status for the run.
+/// With `jsonl`, each stage is printed as a JSON line the moment it finishes
+/// (`{"stage", "continue", "decision", "elapsed_ms", ...}`), followed by a
+/// `{"summary": true, ...}` line, instead of the human output
+fn run_all_stages(
+    hook_input: &serde_json::Value,
+    strict_json: bool,
+    jsonl: bool,
+    sinks: ResultSinks,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> i32 {
+    let emit = |line: serde_json::Value| {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    };
+    if !jsonl {
+        println!();
+    }
+    let mut outcomes: Vec<(&str, ColoredString)> = Vec::new();
+    let mut exit_code = 0;
+    let mut stopped_at = None;
+    let run_start = Instant::now();
+    let width = hook_opts.stages.iter().map(|s| s.chars().count()).max().unwrap_or(0).max(6);
+
+    for stage in hook_opts.stages.iter().map(String::as_str) {
+        if exit_code != 0 {
+            outcomes.push((stage, "⏭  SKIPPED".dimmed()));
+            continue;
+        }
+
+        let start = Instant::now();
+        let output = call_python_hook(stage, hook_input, hook_opts);
+        let elapsed = start.elapsed();
+        sinks.record(stage, hook_input, &output, strict_json, elapsed, &hook_opts.decision_map);
+
+        let result =
+            output.and_then(|output| parse_hook_output(&output, strict_json).map_err(HookError::InvalidOutput));
+
+        if jsonl {
+            let mut line = serde_json::json!({ "stage": stage, "elapsed_ms": elapsed.as_millis() as u64 });
+            match &result {
+                Ok(parsed) => {
+                    let (decision, warning) = parsed.resolve_decision(&hook_opts.decision_map);
+                    line["continue"] = serde_json::json!(decision.allows_continue());
+                    line["decision"] = serde_json::json!(decision.as_str());
+                    let message = parsed.system_message.as_deref().map(|m| masker.mask(m));
+                    line["systemMessage"] = serde_json::json!(message);
+                    if let Some(warning) = warning {
+                        line["warning"] = serde_json::json!(warning);
+                    }
+                    if !decision.allows_continue() {
+                        exit_code = EXIT_BLOCKED;
+                    }
+                }
+                Err(e) => {
+                    line["error"] = serde_json::json!(masker.mask(&e.to_string()));
+                    exit_code = e.exit_code();
+                }
+            }
+            if exit_code != 0 {
+                stopped_at = Some(stage);
+            }
+            emit(line);
+            continue;
+        }
+
+        match result {
+            Ok(parsed) => {
+                let (decision, warning) = parsed.resolve_decision(&hook_opts.decision_map);
+                println!("  {:<width$} {} ({:?})", stage, decision.label(), elapsed);
+                if let Some(warning) = warning {
+                    println!("  {:<width$} {} {}", "", "Warning:".yellow(), warning);
+                }
+                if !decision.allows_continue() {
+                    for line in parsed.system_message.as_deref().unwrap_or("").lines() {
+                        println!("  {:<width$} {}", "", masker.mask(line));
+                    }
+                    exit_code = EXIT_BLOCKED;
+                }
+                outcomes.push((stage, decision.label()));
+            }
+            Err(e) => {
+                let message = masker.mask(&e.to_string());
+                println!("  {:<width$} {} {} ({:?})", stage, "Error:".red().bold(), message, elapsed);
+                outcomes.push((stage, "💥 ERROR".red()));
+                exit_code = e.exit_code();
+            }
+        }
+    }
+
+    if jsonl {
+        emit(serde_json::json!({
+            "summary": true,
+            "continue": exit_code == 0,
+            "stopped_at": stopped_at,
+            // Only skipped stages are pushed to outcomes in this mode
+            "skipped": outcomes.iter().map(|(stage, _)| *stage).collect::<Vec<_>>(),
+            "elapsed_ms": run_start.elapsed().as_millis() as u64,
+            "exit_code": exit_code,
+        }));
+        return exit_code;
+    }
+
+    println!("\n{}", "Summary:".green().bold());
+    for (stage, label) in &outcomes {
+        println!("  {:<width$} {}", stage, label);
+    }
+
+    exit_code
+}
+
+/// A seed from the system RNG (the OS-seeded keys std uses for HashMap), for runs without --seed
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
+/// SplitMix64: tiny, seedable and good enough to pick snippet variations
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[(self.next_u64() % items.len() as u64) as usize]
+    }
+}
+
+/// Kinds of flaw Fuzz injects, in the order its summary lists them
+const FUZZ_FLAWS: [&str; 3] = ["null-deref", "off-by-one", "unsanitized-input"];
+
+/// Builds a small Rust function containing one flaw, with randomized names
+/// and no comment pointing at the problem
+fn fuzz_snippet(rng: &mut SplitMix64, flaw: &str) -> String {
+    let subject = rng.pick(&["user", "order", "item", "record", "session", "account"]);
+    let verb = rng.pick(&["load", "find", "fetch", "resolve", "lookup"]);
+    let var = rng.pick(&["value", "entry", "data", "result", "current"]);
+    let func = format!("{}_{}", verb, subject);
+
+    match flaw {
+        "null-deref" => match rng.next_u64() % 2 {
+            0 => format!(
+                "fn {func}(ids: &[u32], id: u32) -> u32 {{\n    \
+                 let {var}: *const u32 = ids.iter().find(|x| **x == id).map_or(std::ptr::null(), |x| x);\n    \
+                 unsafe {{ *{var} }}\n}}\n"
+            ),
+            _ => format!(
+                "use std::collections::HashMap;\n\n\
+                 fn {func}(map: &HashMap<String, String>, key: &str) -> usize {{\n    \
+                 let {var} = map.get(key);\n    \
+                 {var}.unwrap().len()\n}}\n"
+            ),
+        },
+        "off-by-one" => match rng.next_u64() % 2 {
+            0 => format!(
+                "fn {func}(items: &[i64]) -> i64 {{\n    \
+                 let mut {var} = 0;\n    \
+                 for i in 0..=items.len() {{\n        {var} += items[i];\n    }}\n    \
+                 {var}\n}}\n"
+            ),
+            _ => format!(
+                "fn {func}<T>(items: &[T]) -> &T {{\n    \
+                 let {var} = items.len();\n    \
+                 &items[{var}]\n}}\n"
+            ),
+        },
+        _ => match rng.next_u64() % 2 {
+            0 => format!(
+                "fn {func}(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<usize> {{\n    \
+                 let {var} = format!(\"DELETE FROM {subject}s WHERE name = '{{}}'\", name);\n    \
+                 conn.execute(&{var}, [])\n}}\n"
+            ),
+            _ => format!(
+                "use std::process::Command;\n\n\
+                 fn {func}(path: &str) -> std::io::Result<std::process::Output> {{\n    \
+                 let {var} = format!(\"cat {{}}\", path);\n    \
+                 Command::new(\"sh\").arg(\"-c\").arg({var}).output()\n}}\n"
+            ),
+        },
+    }
+}
+
+/// Generates `iterations` flawed snippets from `seed`, sends each as an Edit
+/// and reports how many were blocked, overall and per kind of flaw
+fn fuzz_hook(iterations: u32, seed: Option<u64>, stage: &str, hook_opts: &HookOptions, masker: &PathMasker) {
+    let seed = seed.unwrap_or_else(random_seed);
+    println!("{}", format!("=== Fuzzing Hook (stage: {}, seed: {}) ===", stage, seed).cyan().bold());
+
+    let mut rng = SplitMix64(seed);
+    // (caught, missed, errors) per entry of FUZZ_FLAWS
+    let mut tally = [(0u32, 0u32, 0u32); FUZZ_FLAWS.len()];
+
+    println!();
+    for i in 1..=iterations {
+        let kind = (rng.next_u64() % FUZZ_FLAWS.len() as u64) as usize;
+        let code = fuzz_snippet(&mut rng, FUZZ_FLAWS[kind]);
+        let input = edit_hook_input(&format!("fuzz-{}-{}", seed, i), &format!("fuzz_{}.rs", i), "", &code);
+        let result = call_python_hook(stage, &input, hook_opts)
+            .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput))
+            .map(|parsed| parsed.resolve_decision(&hook_opts.decision_map).0);
+
+        let verdict = match result {
+            Ok(decision) if !decision.allows_continue() => {
+                tally[kind].0 += 1;
+                "caught".green()
+            }
+            Ok(_) => {
+                tally[kind].1 += 1;
+                "MISSED".red().bold()
+            }
+            Err(e) => {
+                tally[kind].2 += 1;
+                format!("💥 ERROR {}", masker.mask(&e.to_string())).red()
+            }
+        };
+        println!("  [{:>3}] {:<18} {}", i, FUZZ_FLAWS[kind], verdict);
+    }
+
+    let rate = |caught: u32, missed: u32| {
+        let judged = caught + missed;
+        if judged > 0 { format!("{:.1}%", caught as f64 * 100.0 / judged as f64) } else { "n/a".to_string() }
+    };
+    println!("\n{}", "Catch Rate:".green().bold());
+    for (flaw, (caught, missed, errors)) in FUZZ_FLAWS.iter().zip(tally) {
+        let errors = if errors > 0 { format!(", {} errors", errors) } else { String::new() };
+        println!("  {:<18} {:>6}  ({}/{} caught{})", flaw, rate(caught, missed), caught, caught + missed, errors);
+    }
+    let (caught, missed, errors) =
+        tally.iter().fold((0, 0, 0), |(c, m, e), (tc, tm, te)| (c + tc, m + tm, e + te));
+    println!("  {:<18} {:>6}  ({}/{} caught)", "overall", rate(caught, missed), caught, caught + missed);
+    if errors > 0 {
+        println!("  Errors: {}", errors.to_string().red());
+    }
+    println!("\n{}", format!("Reproduce with --seed {}", seed).dimmed());
+}
+
+/// Makes one hook call with the bench Edit and prints where its time went.
+/// Returns false when the call fails.
+fn profile_hook(stage: &str, hook_opts: &HookOptions, masker: &PathMasker) -> bool {
+    println!("{}", format!("=== Profiling Hook (stage: {}) ===", stage).cyan().bold());
+
+    let mut input = bench_input(1, "fn new() {}");
+    input["session_id"] = serde_json::json!(format!("profile-{}", chrono::Utc::now().timestamp()));
+    let wrapper = hook_wrapper(stage, &input, hook_opts);
+
+    let result = spawn_hook_timed(&wrapper, hook_opts);
+    let (output, phases) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            println!("\n{} {}", "Error:".red().bold(), masker.mask(&e.to_string()));
+            return false;
+        }
+    };
+
+    let parse_start = Instant::now();
+    let parsed = if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim_end().to_string();
+        Err(HookError::NonZeroExit { code: output.status.code(), stderr }.to_string())
+    } else {
+        match std::str::from_utf8(&output.stdout) {
+            Ok(stdout) => parse_hook_output(stdout, false),
+            Err(_) => Err(HookError::InvalidUtf8 { bytes: output.stdout.len() }.to_string()),
+        }
+    };
+    let parse = parse_start.elapsed();
+
+    let rows = [
+        ("Spawn", phases.spawn),
+        ("Write", phases.write),
+        ("Wait", phases.wait),
+        ("Read", phases.read),
+        ("Parse", parse),
+    ];
+    let total: std::time::Duration = rows.iter().map(|(_, d)| *d).sum();
+    let width = rows.iter().map(|(_, d)| format_ms(*d).len()).max().unwrap_or(0);
+
+    println!("\n{}", "Phases:".green().bold());
+    for (label, duration) in rows {
+        let share = duration.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0;
+        println!("  {:<6}  {:>width$}  {:>5.1}%", label, format_ms(duration), share);
+    }
+    println!("  {:<6}  {:>width$}", "Total", format_ms(total));
+
+    // Wait covers interpreter startup as well as the review itself
+    if let Some(startup) = measure_python_startup(hook_opts) {
+        println!(
+            "\n{}",
+            format!(
+                "Wait includes ≈{} of Python startup (mean of {} `python -c pass` runs), \
+                 leaving ≈{} for the hook itself",
+                format_ms(startup),
+                STARTUP_SAMPLES,
+                format_ms(phases.wait.saturating_sub(startup))
+            )
+            .dimmed()
+        );
+    }
+
+    match parsed {
+        Ok(parsed) => {
+            println!("\n  Decision: {}", parsed.resolve_decision(&hook_opts.decision_map).0.label());
+            true
+        }
+        Err(e) => {
+            println!("\n  {} {}", "Error:".red().bold(), masker.mask(&e));
+            false
+        }
+    }
+}
+
+/// Runs the same edit through every stage, unlike --all-stages never stopping
+/// at a block, and points out the earliest stage that blocks it
+fn compare_stages(path: &std::path::Path, hook_opts: &HookOptions, masker: &PathMasker) {
+    let shown = masker.mask(&path.display().to_string());
+    println!("{}", format!("=== Comparing Stages ({}) ===", shown).cyan().bold());
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), shown, e);
+            std::process::exit(1);
+        }
+    };
+    let session_id = format!("compare-{}", chrono::Utc::now().timestamp());
+    let hook_input = edit_hook_input(&session_id, &path.display().to_string(), "", &content);
+
+    let mut first_block = None;
+    let width = hook_opts.stages.iter().map(|s| s.chars().count()).max().unwrap_or(0).max(6);
+    println!("\n  {:<width$}  {:<12}  {:>10}  Message", "Stage", "Decision", "Time");
+    for stage in hook_opts.stages.iter().map(String::as_str) {
+        let start = Instant::now();
+        let result = call_python_hook(stage, &hook_input, hook_opts)
+            .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput));
+        let elapsed = start.elapsed();
+
+        match result {
+            Ok(parsed) => {
+                let (decision, _) = parsed.resolve_decision(&hook_opts.decision_map);
+                if !decision.allows_continue() && first_block.is_none() {
+                    first_block = Some(stage);
+                }
+                let message = parsed.system_message.as_deref().and_then(|m| m.lines().next()).unwrap_or("");
+                println!(
+                    "  {:<width$}  {:<12}  {:>10.2?}  {}",
+                    stage,
+                    decision.label(),
+                    elapsed,
+                    masker.mask(message)
+                );
+            }
+            Err(e) => {
+                let message = masker.mask(&e.to_string());
+                println!("  {:<width$}  {:<12}  {:>10.2?}  {}", stage, "💥 ERROR".red(), elapsed, message);
+            }
+        }
+    }
+
+    match first_block {
+        Some(stage) => println!("\n{} {}", "First blocking stage:".red().bold(), stage.red().bold()),
+        None => println!("\n{}", "No stage blocks this change".green().bold()),
+    }
+}
+
+/// Scores the hook against a corpus with block as the positive class: a block
+/// where continue was expected is a false positive, the reverse a false negative
+/// Prints the score table. Returns false only when --snapshot verification finds drift.
+fn score_corpus(
+    dir: &std::path::Path,
+    stage: &str,
+    expect: Expectation,
+    snapshot: &SnapshotArgs,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> bool {
+    println!("{}", format!("=== Scoring {} (stage: {}) ===", dir.display(), stage).cyan().bold());
+
+    let mut files: Vec<std::path::PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect(),
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    files.sort();
+
+    let (mut tp, mut tn, mut fp, mut fn_, mut errors) = (0, 0, 0, 0, 0);
+    let mut results = std::collections::BTreeMap::new();
+
+    println!();
+    for (index, path) in files.iter().enumerate() {
+        let name = masker.mask(&path.display().to_string());
+        let outcome = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read: {}", e))
+            .and_then(|content| {
+                let session_id = format!("score-{}-{}", chrono::Utc::now().timestamp(), index + 1);
+                let input = edit_hook_input(&session_id, &path.display().to_string(), "", &content);
+                call_python_hook(stage, &input, hook_opts)
+                    .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput))
+                    .map(|parsed| (parsed.resolve_decision(&hook_opts.decision_map).0, parsed.system_message))
+                    .map_err(|e| e.to_string())
+            });
+        let key = path.strip_prefix(dir).unwrap_or(path).display().to_string();
+        results.insert(key, SnapshotEntry::new(&outcome));
+
+        match outcome.map(|(decision, _)| decision) {
+            Ok(decision) => {
+                let blocked = !decision.allows_continue();
+                let matched = blocked == (expect == Expectation::Block);
+                match (blocked, matched) {
+                    (true, true) => tp += 1,
+                    (true, false) => fp += 1,
+                    (false, true) => tn += 1,
+                    (false, false) => fn_ += 1,
+                }
+                let verdict = if matched { "match".green() } else { "MISMATCH".red().bold() };
+                println!("  {}  {}  {}", decision.label(), verdict, name);
+            }
+            Err(e) => {
+                errors += 1;
+                println!("  {}  {}  {}", "💥 ERROR".red(), name, masker.mask(&e));
+            }
+        }
+    }
+
+    let scored = tp + tn + fp + fn_;
+    let ratio = |num: i32, den: i32| {
+        if den > 0 { format!("{:.1}%", num as f64 * 100.0 / den as f64) } else { "n/a".to_string() }
+    };
+
+    println!("\n{}", "Score:".green().bold());
+    println!("  Files: {} ({} scored, {} errors)", files.len(), scored, errors);
+    println!("  Accuracy: {}", ratio(tp + tn, scored));
+    println!("  False positives (blocked, expected continue): {}", fp);
+    println!("  False negatives (continued, expected block): {}", fn_);
+    println!("  Precision (block): {}", ratio(tp, tp + fp));
+    println!("  Recall (block): {}", ratio(tp, tp + fn_));
+
+    check_snapshot(snapshot, &results)
+}
+
+/// Runs each case from `dir/suite.json` as an Edit of its file and prints a
+/// PASS/FAIL table. Returns whether every case passed (and matched --snapshot).
+fn run_test_suite(
+    dir: &std::path::Path,
+    snapshot: &SnapshotArgs,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> bool {
+    println!("{}", format!("=== Test Suite ({}) ===", dir.display()).cyan().bold());
+
+    let manifest = dir.join("suite.json");
+    let cases: std::collections::BTreeMap<String, SuiteCase> = match std::fs::read_to_string(&manifest)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+    {
+        Ok(cases) => cases,
+        Err(e) => {
+            eprintln!("{} Failed to load {}: {}", "Error:".red().bold(), manifest.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let width = cases.keys().map(|f| f.chars().count()).max().unwrap_or(0).max("Case".len());
+    let (mut passed, mut failed) = (0, 0);
+    let mut results = std::collections::BTreeMap::new();
+
+    println!("\n  {:<width$}  {:<6}  {:<8}  {:<8}  Result", "Case", "Stage", "Expected", "Actual", width = width);
+    for (index, (file, case)) in cases.iter().enumerate() {
+        let path = dir.join(file);
+        let outcome = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read: {}", e))
+            .and_then(|content| {
+                let session_id = format!("suite-{}-{}", chrono::Utc::now().timestamp(), index + 1);
+                let input = edit_hook_input(&session_id, &path.display().to_string(), "", &content);
+                call_python_hook(&case.stage, &input, hook_opts)
+                    .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput))
+                    .map(|parsed| (parsed.resolve_decision(&hook_opts.decision_map).0, parsed.system_message))
+                    .map_err(|e| e.to_string())
+            });
+        results.insert(file.clone(), SnapshotEntry::new(&outcome));
+        let decision = outcome.map(|(decision, _)| decision);
+
+        let actual = match &decision {
+            Ok(decision) if decision.allows_continue() => Expectation::Continue.as_str(),
+            Ok(_) => Expectation::Block.as_str(),
+            Err(_) => "error",
+        };
+        let verdict = if actual == case.expect.as_str() {
+            passed += 1;
+            "PASS".green().bold()
+        } else {
+            failed += 1;
+            "FAIL".red().bold()
+        };
+        println!(
+            "  {:<width$}  {:<6}  {:<8}  {:<8}  {}",
+            file,
+            case.stage,
+            case.expect.as_str(),
+            actual,
+            verdict,
+            width = width
+        );
+        if let Err(e) = decision {
+            println!("  {:<width$}  {}", "", masker.mask(&e).dimmed(), width = width);
+        }
+    }
+
+    println!("\n{}", "Tally:".green().bold());
+    println!("  Passed: {}", passed.to_string().green());
+    let failed_label = if failed > 0 { failed.to_string().red() } else { failed.to_string().normal() };
+    println!("  Failed: {}", failed_label);
+
+    let snapshot_ok = check_snapshot(snapshot, &results);
+    failed == 0 && snapshot_ok
+}
+
+/// One file's recorded outcome in a --snapshot file
+#[derive(Serialize, Deserialize, PartialEq)]
+struct SnapshotEntry {
+    /// continue/block/warn, or "error" when the hook call failed
+    decision: String,
+    /// FNV-1a hash of the system message, so wording changes show up without storing it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_hash: Option<String>,
+}
+
+impl SnapshotEntry {
+    fn new(outcome: &Result<(Decision, Option<String>), String>) -> Self {
+        match outcome {
+            Ok((decision, message)) => SnapshotEntry {
+                decision: decision.as_str().to_string(),
+                message_hash: message.as_deref().map(fnv1a_hex),
+            },
+            Err(_) => SnapshotEntry { decision: "error".to_string(), message_hash: None },
+        }
+    }
+}
+
+/// 64-bit FNV-1a, stable across platforms and Rust versions unlike DefaultHasher
+fn fnv1a_hex(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Writes `results` to the snapshot with --update-snapshots, otherwise compares
+/// against it and prints every difference. Returns false on drift or a bad snapshot.
+fn check_snapshot(args: &SnapshotArgs, results: &std::collections::BTreeMap<String, SnapshotEntry>) -> bool {
+    let Some(path) = &args.snapshot else {
+        return true;
+    };
+
+    if args.update_snapshots {
+        let written = serde_json::to_string_pretty(results)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(path, json + "\n").map_err(|e| e.to_string()));
+        return match written {
+            Ok(()) => {
+                println!("\n{} {} ({} entries)", "Snapshot written to".green(), path.display(), results.len());
+                true
+            }
+            Err(e) => {
+                eprintln!("{} Failed to write {}: {}", "Error:".red().bold(), path.display(), e);
+                false
+            }
+        };
+    }
+
+    let expected: std::collections::BTreeMap<String, SnapshotEntry> = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+    {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!(
+                "{} Failed to load snapshot {}: {} (capture one with --update-snapshots)",
+                "Error:".red().bold(),
+                path.display(),
+                e
+            );
+            return false;
+        }
+    };
+
+    println!("\n{}", format!("Snapshot ({}):", path.display()).green().bold());
+    let mut drift = 0;
+    for (file, before) in &expected {
+        match results.get(file) {
+            None => {
+                drift += 1;
+                println!("  {:<10} {}", "MISSING".red().bold(), file);
+            }
+            Some(after) if after.decision != before.decision => {
+                drift += 1;
+                println!("  {:<10} {}  {} → {}", "CHANGED".red().bold(), file, before.decision, after.decision);
+            }
+            Some(after) if after.message_hash != before.message_hash => {
+                drift += 1;
+                println!("  {:<10} {}  system message differs", "MESSAGE".yellow().bold(), file);
+            }
+            Some(_) => {}
+        }
+    }
+    for file in results.keys().filter(|file| !expected.contains_key(*file)) {
+        drift += 1;
+        println!("  {:<10} {}", "NEW".yellow().bold(), file);
+    }
+
+    if drift == 0 {
+        println!("  {} all {} entries match", "✅".green(), expected.len());
+        true
+    } else {
+        println!("\n{}", format!("❌ {} entries drifted from the snapshot", drift).red().bold());
+        false
+    }
+}
+
+impl ResultSinks<'_> {
+    /// Appends one stage's outcome to whichever of --save and --output-file were given
+    fn record(
+        &self,
+        stage: &str,
+        hook_input: &serde_json::Value,
+        output: &Result<String, HookError>,
+        strict_json: bool,
+        elapsed: std::time::Duration,
+        decision_map: &HashMap<String, Decision>,
+    ) {
+        if self.save.is_none() && self.output_file.is_none() {
+            return;
+        }
+        let parsed = output
+            .as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|raw| parse_hook_output(raw, strict_json));
+
+        if let Some(path) = self.save {
+            save_result(path, stage, hook_input, parsed.as_ref().map_err(String::clone), decision_map);
+        }
+        if let Some(path) = self.output_file {
+            let decision = parsed.as_ref().ok().map(|p| p.resolve_decision(decision_map).0.as_str());
+            let record = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                "stage": stage,
+                "input": hook_input,
+                "raw_output": output.as_ref().ok(),
+                "decision": decision,
+                "error": parsed.as_ref().err(),
+                "elapsed_ms": elapsed.as_millis() as u64,
+            });
+            if let Err(e) = append_json_line(path, &record) {
+                eprintln!("{} Failed to write {}: {}", "Warning:".yellow(), path.display(), e);
+            }
+        }
+    }
+}
+
+fn append_json_line(path: &std::path::Path, record: &impl Serialize) -> std::io::Result<()> {
+    let line = serde_json::to_string(record)?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Appends one stage's outcome to an Invoke --save file
+fn save_result(
+    path: &std::path::Path,
+    stage: &str,
+    hook_input: &serde_json::Value,
+    result: Result<&HookOutput, String>,
+    decision_map: &HashMap<String, Decision>,
+) {
+    let (decision, system_message, error) = match result {
+        Ok(parsed) => {
+            let (decision, _) = parsed.resolve_decision(decision_map);
+            (Some(decision.as_str().to_string()), parsed.system_message.clone(), None)
+        }
+        Err(e) => (None, None, Some(e)),
+    };
+    let saved = SavedResult {
+        session_id: hook_input["session_id"].as_str().unwrap_or_default().to_string(),
+        stage: stage.to_string(),
+        decision,
+        system_message,
+        error,
+    };
+
+    if let Err(e) = append_json_line(path, &saved) {
+        eprintln!("{} Failed to save result to {}: {}", "Warning:".yellow(), path.display(), e);
+    }
+}
+
+/// Loads an Invoke --save file keyed by (session_id, stage); later lines win
+fn load_saved_results(
+    path: &std::path::Path,
+) -> Result<std::collections::BTreeMap<(String, String), SavedResult>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut results = std::collections::BTreeMap::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let saved: SavedResult = serde_json::from_str(line)
+            .map_err(|e| format!("{} line {}: {}", path.display(), index + 1, e))?;
+        results.insert((saved.session_id.clone(), saved.stage.clone()), saved);
+    }
+    Ok(results)
+}
+
+/// Prints decision changes between two saved runs. Returns false when any
+/// session regressed from continue to block.
+fn diff_saved_results(baseline_path: &std::path::Path, current_path: &std::path::Path) -> bool {
+    println!("{}", "=== Hook Result Diff ===".cyan().bold());
+
+    let (baseline, current) = match (load_saved_results(baseline_path), load_saved_results(current_path)) {
+        (Ok(baseline), Ok(current)) => (baseline, current),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{} {}", "Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let outcome = |saved: &SavedResult| saved.decision.clone().unwrap_or_else(|| "error".to_string());
+    let mut compared = 0;
+    let mut regressions = 0;
+    let mut improvements = 0;
+    let mut other_changes = 0;
+    let mut message_changes = 0;
+
+    println!();
+    for (key, before) in &baseline {
+        let Some(after) = current.get(key) else { continue };
+        compared += 1;
+        if before.system_message != after.system_message {
+            message_changes += 1;
+        }
+
+        let (was, now) = (outcome(before), outcome(after));
+        if was == now {
+            continue;
+        }
+        let label = match (was.as_str(), now.as_str()) {
+            ("continue", "block") => {
+                regressions += 1;
+                "REGRESSION".red().bold()
+            }
+            ("block", "continue") => {
+                improvements += 1;
+                "IMPROVEMENT".green().bold()
+            }
+            _ => {
+                other_changes += 1;
+                "CHANGED".yellow()
+            }
+        };
+        println!("  {:<12} {} [{}]  {} → {}", label, key.0, key.1, was, now);
+    }
+
+    let missing = baseline.keys().filter(|key| !current.contains_key(*key)).count();
+    let added = current.keys().filter(|key| !baseline.contains_key(*key)).count();
+
+    println!("\n{}", "Summary:".green().bold());
+    println!("  Compared: {}", compared);
+    println!("  Regressions (continue → block): {}", regressions.to_string().red());
+    println!("  Improvements (block → continue): {}", improvements.to_string().green());
+    if other_changes > 0 {
+        println!("  Other decision changes: {}", other_changes.to_string().yellow());
+    }
+    println!("  System messages differing: {}", message_changes);
+    if missing > 0 {
+        println!("  Only in baseline: {}", missing);
+    }
+    if added > 0 {
+        println!("  Only in current: {}", added);
+    }
+
+    regressions == 0
+}
+
+/// Language names for common source file extensions
+const LANGUAGES: &[(&[&str], &str)] = &[
+    (&["rs"], "rust"),
+    (&["py", "pyi"], "python"),
+    (&["js", "jsx", "mjs", "cjs"], "javascript"),
+    (&["ts", "tsx", "mts", "cts"], "typescript"),
+    (&["go"], "go"),
+    (&["java"], "java"),
+    (&["kt", "kts"], "kotlin"),
+    (&["c", "h"], "c"),
+    (&["cc", "cpp", "cxx", "hpp", "hh"], "cpp"),
+    (&["cs"], "csharp"),
+    (&["rb"], "ruby"),
+    (&["php"], "php"),
+    (&["swift"], "swift"),
+    (&["scala"], "scala"),
+    (&["sh", "bash", "zsh"], "shell"),
+    (&["sql"], "sql"),
+];
+
+fn language_for_path(path: &str) -> Option<&'static str> {
+    let extension = std::path::Path::new(path).extension()?.to_str()?.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(extensions, _)| extensions.contains(&extension.as_str()))
+        .map(|(_, language)| *language)
+}
+
+/// Sets tool_input.language from --language, or from the file_path extension
+/// when the input doesn't already carry one
+fn set_language(input: &mut serde_json::Value, explicit: Option<&str>) {
+    let Some(tool_input) = input.get_mut("tool_input").and_then(|t| t.as_object_mut()) else {
+        return;
+    };
+    let language = match explicit {
+        Some(language) => language,
+        None if tool_input.contains_key("language") => return,
+        None => match tool_input.get("file_path").and_then(|f| f.as_str()).and_then(language_for_path) {
+            Some(language) => language,
+            None => return,
+        },
+    };
+    tool_input.insert("language".to_string(), serde_json::json!(language));
+}
+
+/// A literal that can contain comment markers without starting a comment
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Literal {
+    /// Ends at the next delimiter not escaped with a backslash
+    Quoted(&'static str),
+    /// Ends at the next delimiter; backslashes are plain text (Go raw strings)
+    Raw(&'static str),
+    /// Rust raw strings: r"..", r#".."#, br".."
+    RustRaw,
+    /// Rust char literals ('x', '\n'), as opposed to lifetimes and labels ('a)
+    RustChar,
+    /// JavaScript regex literals, where an expression can start
+    Regex,
+}
+
+/// How a language writes comments, and the literals those markers can hide in
+struct CommentSyntax {
+    languages: &'static [&'static str],
+    line: &'static str,
+    /// Block comment open and close markers
+    block: Option<(&'static str, &'static str)>,
+    /// Rust block comments nest; C-style ones end at the first close marker
+    nested: bool,
+    /// Tried in order, so longer delimiters come first
+    literals: &'static [Literal],
+}
+
+/// Comment syntax for the languages --strip-comments understands
+const COMMENT_SYNTAX: &[CommentSyntax] = &[
+    CommentSyntax {
+        languages: &["rust"],
+        line: "//",
+        block: Some(("/*", "*/")),
+        nested: true,
+        literals: &[Literal::RustRaw, Literal::Quoted("\""), Literal::RustChar],
+    },
+    CommentSyntax {
+        languages: &["python"],
+        line: "#",
+        block: None,
+        nested: false,
+        literals: &[
+            Literal::Quoted("\"\"\""),
+            Literal::Quoted("'''"),
+            Literal::Quoted("\""),
+            Literal::Quoted("'"),
+        ],
+    },
+    CommentSyntax {
+        languages: &["javascript", "typescript"],
+        line: "//",
+        block: Some(("/*", "*/")),
+        nested: false,
+        literals: &[Literal::Quoted("\""), Literal::Quoted("'"), Literal::Quoted("`"), Literal::Regex],
+    },
+    CommentSyntax {
+        languages: &["go"],
+        line: "//",
+        block: Some(("/*", "*/")),
+        nested: false,
+        literals: &[Literal::Quoted("\""), Literal::Raw("`"), Literal::Quoted("'")],
+    },
+];
+
+/// Keywords after which a `/` starts a regex rather than dividing
+const REGEX_KEYWORDS: &[&str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void", "throw", "case", "do", "else", "yield",
+    "await",
+];
+
+fn comment_syntax(language: &str) -> Option<&'static CommentSyntax> {
+    let language = language.to_lowercase();
+    COMMENT_SYNTAX.iter().find(|syntax| syntax.languages.contains(&language.as_str()))
+}
+
+impl CommentSyntax {
+    /// Removes comments, keeping their newlines so line numbers still match the
+    /// original. A block comment inside a line becomes a space, so the tokens on
+    /// either side don't merge; whitespace left before a line end is trimmed.
+    fn strip(&self, code: &str) -> String {
+        let chars: Vec<char> = code.chars().collect();
+        let mut stripped = String::with_capacity(code.len());
+        let mut i = 0;
+        while i < chars.len() {
+            if starts_at(&chars, i, self.line) {
+                while stripped.ends_with([' ', '\t']) {
+                    stripped.pop();
+                }
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            } else if let Some((open, close)) = self.block.filter(|(open, _)| starts_at(&chars, i, open)) {
+                let end = self.block_end(&chars, i, open, close);
+                let newlines: String = chars[i..end].iter().filter(|&&c| c == '\n').collect();
+                if matches!(chars.get(end), None | Some('\n')) {
+                    while stripped.ends_with([' ', '\t']) {
+                        stripped.pop();
+                    }
+                } else if newlines.is_empty() {
+                    stripped.push(' ');
+                }
+                stripped.push_str(&newlines);
+                i = end;
+            } else if let Some(end) = self.literal_end(&chars, i) {
+                stripped.extend(&chars[i..end]);
+                i = end;
+            } else {
+                stripped.push(chars[i]);
+                i += 1;
+            }
+        }
+        stripped
+    }
+
+    /// Index just past the block comment opening at `start`, or the end of an unterminated one
+    fn block_end(&self, chars: &[char], start: usize, open: &str, close: &str) -> usize {
+        let mut depth = 0;
+        let mut i = start;
+        while i < chars.len() {
+            if starts_at(chars, i, open) && (depth == 0 || self.nested) {
+                depth += 1;
+                i += open.chars().count();
+            } else if starts_at(chars, i, close) {
+                depth -= 1;
+                i += close.chars().count();
+                if depth == 0 {
+                    return i;
+                }
+            } else {
+                i += 1;
+            }
+        }
+        chars.len()
+    }
+
+    /// Index just past the literal starting at `i`, if one does
+    fn literal_end(&self, chars: &[char], i: usize) -> Option<usize> {
+        self.literals.iter().find_map(|literal| match *literal {
+            Literal::Quoted(delimiter) | Literal::Raw(delimiter) if starts_at(chars, i, delimiter) => {
+                let escapes = matches!(literal, Literal::Quoted(_));
+                Some(quoted_end(chars, i + delimiter.chars().count(), delimiter, escapes))
+            }
+            Literal::Quoted(_) | Literal::Raw(_) => None,
+            Literal::RustRaw => rust_raw_end(chars, i),
+            Literal::RustChar => rust_char_end(chars, i),
+            Literal::Regex => regex_end(chars, i),
+        })
+    }
+}
+
+fn starts_at(chars: &[char], i: usize, pattern: &str) -> bool {
+    pattern.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c))
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Index just past the closing `delimiter`, scanning from `from`
+fn quoted_end(chars: &[char], from: usize, delimiter: &str, escapes: bool) -> usize {
+    let mut i = from;
+    while i < chars.len() {
+        if escapes && chars[i] == '\\' {
+            i += 2;
+        } else if starts_at(chars, i, delimiter) {
+            return i + delimiter.chars().count();
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+fn rust_raw_end(chars: &[char], i: usize) -> Option<usize> {
+    // Only where a token starts, not at the `r` of an identifier like `for`
+    if i > 0 && is_ident_char(chars[i - 1]) {
+        return None;
+    }
+    let mut j = i + usize::from(chars.get(i) == Some(&'b'));
+    if chars.get(j) != Some(&'r') {
+        return None;
+    }
+    j += 1;
+    let hashes = chars[j..].iter().take_while(|&&c| c == '#').count();
+    j += hashes;
+    if chars.get(j) != Some(&'"') {
+        return None;
+    }
+    j += 1;
+    while j < chars.len() {
+        if chars[j] == '"' && chars[j + 1..].iter().take_while(|&&c| c == '#').count() >= hashes {
+            return Some(j + 1 + hashes);
+        }
+        j += 1;
+    }
+    Some(chars.len())
+}
+
+fn rust_char_end(chars: &[char], i: usize) -> Option<usize> {
+    if chars[i] != '\'' {
+        return None;
+    }
+    match chars.get(i + 1)? {
+        '\\' => Some(quoted_end(chars, i + 1, "'", true)),
+        _ if chars.get(i + 2) == Some(&'\'') => Some(i + 3),
+        // 'a without a closing quote is a lifetime or loop label
+        _ => None,
+    }
+}
+
+fn regex_end(chars: &[char], i: usize) -> Option<usize> {
+    if chars[i] != '/' {
+        return None;
+    }
+    // A regex can only start where an expression can; elsewhere `/` divides
+    let before: Vec<char> = chars[..i].iter().rev().skip_while(|c| c.is_whitespace()).copied().collect();
+    let starts_expression = match before.first() {
+        None => true,
+        Some(&c) if is_ident_char(c) => {
+            let len = before.iter().take_while(|&&c| is_ident_char(c)).count();
+            let word: String = before[..len].iter().rev().collect();
+            REGEX_KEYWORDS.contains(&word.as_str())
+        }
+        Some(&c) => "(,=:[!&|?{};+-*%<>~^".contains(c),
+    };
+    if !starts_expression {
+        return None;
+    }
+
+    let mut in_class = false;
+    let mut j = i + 1;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 1,
+            '\n' => return None,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                let flags = chars[j + 1..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+                return Some(j + 1 + flags);
+            }
+            _ => {}
+        }
+        j += 1;
+    }
+    None
+}
+
+/// tool_input.language and its comment markers, for verbose output
+fn describe_language(input: &serde_json::Value) -> String {
+    let Some(language) = input.pointer("/tool_input/language").and_then(|l| l.as_str()) else {
+        return "none detected".to_string();
+    };
+    match comment_syntax(language) {
+        Some(syntax) => {
+            let mut markers = vec![syntax.line.to_string()];
+            if let Some((open, close)) = syntax.block {
+                markers.push(format!("{} {}{}", open, close, if syntax.nested { " (nesting)" } else { "" }));
+            }
+            format!("{} (comments: {})", language, markers.join(", "))
+        }
+        None => format!("{} (comment syntax unknown)", language),
+    }
+}
+
+/// Strips comments from tool_input's code fields (--strip-comments) using tool_input.language
+fn strip_input_comments(input: &mut serde_json::Value) -> Result<(), String> {
+    let Some(tool_input) = input.get_mut("tool_input").and_then(|t| t.as_object_mut()) else {
+        return Ok(());
+    };
+    let language = tool_input.get("language").and_then(|l| l.as_str()).unwrap_or_default();
+    let Some(syntax) = comment_syntax(language) else {
+        let known: Vec<&str> = COMMENT_SYNTAX.iter().flat_map(|syntax| syntax.languages.iter().copied()).collect();
+        let language = if language.is_empty() { "no language".to_string() } else { format!("'{}'", language) };
+        return Err(format!(
+            "--strip-comments doesn't know the comment syntax for {}; pass --language {}",
+            language,
+            known.join("|")
+        ));
+    };
+    for field in ["content", "new_string", "old_string"] {
+        if let Some(serde_json::Value::String(code)) = tool_input.get_mut(field) {
+            *code = syntax.strip(code);
+        }
+    }
+    Ok(())
+}
+
+/// Builds the hook input from --stdin, --old-file/--new-file, --editor, --code or --file, in that order
+fn build_invoke_input(args: &InvokeArgs) -> Result<serde_json::Value, String> {
+    if args.stdin {
+        let mut raw = String::new();
+        std::io::stdin()
+            .read_to_string(&mut raw)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        let input: serde_json::Value =
+            serde_json::from_str(&raw).map_err(|e| format!("stdin is not valid JSON: {}", e))?;
+        // Validate the shape, but forward the original so extra fields like transcript_path survive
+        serde_json::from_value::<HookInput>(input.clone())
+            .map_err(|e| format!("stdin is not a valid HookInput: {}", e))?;
+        return Ok(input);
+    }
+
+    if let (Some(old_file), Some(new_file)) = (&args.old_file, &args.new_file) {
+        let read = |path: &str| {
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+        };
+        let session_id = format!("invoke-{}", chrono::Utc::now().timestamp());
+        return Ok(edit_hook_input(&session_id, new_file, &read(old_file)?, &read(new_file)?));
+    }
+
+    let code_content = if args.editor {
+        read_code_from_editor(args.file.first().map(String::as_str))?
+    } else if let Some(c) = &args.code {
+        c.clone()
+    } else if args.code_stdin {
+        let mut code = String::new();
+        std::io::stdin()
+            .read_to_string(&mut code)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        code
+    } else if let Some(f) = args.file.first() {
+        std::fs::read_to_string(f).unwrap_or_else(|_| "// Could not read file".to_string())
+    } else {
+        "fn example() { /* test code */ }".to_string()
+    };
+
+    let session_id = format!("invoke-{}", chrono::Utc::now().timestamp());
+    let file_path = args.file.first().map(String::as_str).unwrap_or("test.rs");
+    Ok(edit_hook_input(&session_id, file_path, "", &code_content))
+}
+
+/// Opens $EDITOR on a temp file and returns what the user saved
+fn read_code_from_editor(file: Option<&str>) -> Result<String, String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() });
+
+    let extension = file
+        .and_then(|f| std::path::Path::new(f).extension())
+        .and_then(|e| e.to_str())
+        .unwrap_or("rs");
+    let temp_path = std::env::temp_dir().join(format!(
+        "hook-test-{}-{}.{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp(),
+        extension
+    ));
+    std::fs::write(&temp_path, "").map_err(|e| format!("Failed to create temp file: {}", e))?;
+
+    // EDITOR may carry arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&temp_path).status();
+
+    let content = std::fs::read_to_string(&temp_path);
+    let _ = std::fs::remove_file(&temp_path);
+
+    let status = status.map_err(|e| format!("Failed to launch editor '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("Editor '{}' exited with {}", editor, status));
+    }
+
+    let content = content.map_err(|e| format!("Failed to read edited file: {}", e))?;
+    if content.trim().is_empty() {
+        return Err("No code written in editor; nothing to review".to_string());
+    }
+    Ok(content)
+}
+
+/// Makes the first Ctrl-C set the returned flag (after killing any running
+/// hook) instead of exiting; a second one still exits
+fn interrupt_flag() -> &'static std::sync::atomic::AtomicBool {
+    DEFER_INTERRUPT.store(true, std::sync::atomic::Ordering::SeqCst);
+    &INTERRUPTED
+}
+
+/// Sleeps for `interval`, waking early once `stop` returns true
+fn sleep_unless_stopped(interval: std::time::Duration, stop: impl Fn() -> bool) {
+    let wake = Instant::now() + interval;
+    while !stop() && Instant::now() < wake {
+        std::thread::sleep((wake - Instant::now()).min(std::time::Duration::from_millis(50)));
+    }
+}
+
+/// --repeat for --json-stream: `emit` writes one result record per run
+/// (forever for 0) with `interval` between runs; Ctrl-C stops after the run it
+/// cut short. Exits with the highest exit code of any completed run.
+fn run_repeated_json_stream(repeat: u32, interval: std::time::Duration, mut emit: impl FnMut() -> i32) -> i32 {
+    let interrupted = interrupt_flag();
+    let stop = || interrupted.load(std::sync::atomic::Ordering::SeqCst);
+    let mut exit_code = 0;
+    let mut runs = 0;
+
+    while repeat == 0 || runs < repeat {
+        if runs > 0 {
+            sleep_unless_stopped(interval, stop);
+        }
+        if stop() {
+            break;
+        }
+        let code = emit();
+        if stop() {
+            break;
+        }
+        exit_code = exit_code.max(code);
+        runs += 1;
+    }
+    exit_code
+}
+
+/// Runs the input `repeat` times (forever for 0) with `interval` between runs,
+/// keeping a running tally; Ctrl-C stops early and still prints the summary
+fn run_repeated_invoke(
+    stage: &str,
+    hook_input: &serde_json::Value,
+    strict_json: bool,
+    repeat: u32,
+    interval: std::time::Duration,
+    hook_opts: &HookOptions,
+) -> i32 {
+    if hook_opts.verbosity != Verbosity::Quiet {
+        let runs = if repeat == 0 { "until Ctrl-C".to_string() } else { format!("{} times", repeat) };
+        println!("\n{}", format!("Sending to Python hook {}...", runs).yellow());
+    }
+
+    let interrupted = interrupt_flag();
+    let stop = || interrupted.load(std::sync::atomic::Ordering::SeqCst);
+    let mut continued = 0;
+    let mut blocked = 0;
+    let mut warned = 0;
+    let mut errors = 0;
+    let mut timings: Vec<std::time::Duration> = Vec::new();
+
+    while repeat == 0 || timings.len() < repeat as usize {
+        if !timings.is_empty() {
+            sleep_unless_stopped(interval, stop);
+        }
+        if stop() {
+            break;
+        }
+
+        let start = Instant::now();
+        let result = call_python_hook(stage, hook_input, hook_opts);
+        // Ctrl-C kills the hook, so a run cut short says nothing about it
+        if stop() {
+            break;
+        }
+        timings.push(start.elapsed());
+
+        let decision = result
+            .and_then(|output| parse_hook_output(&output, strict_json).map_err(HookError::InvalidOutput))
+            .map(|parsed| parsed.resolve_decision(&hook_opts.decision_map).0);
+        match decision {
+            Ok(Decision::Continue) => continued += 1,
+            Ok(Decision::Block) => blocked += 1,
+            Ok(Decision::Warn) => warned += 1,
+            Err(_) => errors += 1,
+        }
+
+        print!(
+            "\r  Run {}: {} continue, {} block, {} warn, {} error",
+            timings.len(),
+            continued.to_string().green(),
+            blocked.to_string().red(),
+            warned.to_string().yellow(),
+            errors.to_string().yellow()
+        );
+        let _ = std::io::stdout().flush();
+    }
+    println!();
+
+    if stop() {
+        println!("{}", "Interrupted".yellow());
+    }
+    let runs = timings.len();
+    let (Some(min), Some(max)) = (timings.iter().min(), timings.iter().max()) else {
+        println!("\n{}", "No runs completed".yellow());
+        return 0;
+    };
+    let avg = timings.iter().sum::<std::time::Duration>() / runs as u32;
+
+    println!("\n{}", "Decisions:".green().bold());
+    println!("  {}/{} CONTINUE", continued, runs);
+    println!("  {}/{} BLOCKED", blocked, runs);
+    if warned > 0 {
+        println!("  {}/{} WARN", warned, runs);
+    }
+    if errors > 0 {
+        println!("  {}/{} ERROR", errors, runs);
+    }
+
+    println!("\n{}", "Latency:".green().bold());
+    print_stat_table(&[
+        ("Min", format_ms(*min)),
+        ("Average", format_ms(avg)),
+        ("Max", format_ms(*max)),
+        ("Spread", format_ms(*max - *min)),
+    ]);
+
+    let outcomes = [continued, blocked, warned, errors].iter().filter(|&&n| n > 0).count();
+    if outcomes > 1 {
+        println!("\n{}", "⚠️  NONDETERMINISTIC: decisions disagree across runs".red().bold());
+    } else {
+        println!("\n{}", "✅ Stable: all runs agreed".green());
+    }
+
+    if errors > 0 {
+        EXIT_HOOK_ERROR
+    } else if blocked > 0 {
+        EXIT_BLOCKED
+    } else {
+        0
+    }
+}
+
+fn emit_json_result(
+    stage: &str,
+    hook_input: &serde_json::Value,
+    strict_json: bool,
+    stream: JsonStream,
+    hook_opts: &HookOptions,
+    masker: &PathMasker,
+) -> i32 {
+    let start = Instant::now();
+    let result = run_python_hook(stage, hook_input, hook_opts);
+    let elapsed = start.elapsed();
+
+    let mut record = serde_json::json!({
+        "stage": stage,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "process": null,
+    });
+    let mut exit_code = 0;
+
+    match result {
+        Ok(process) => {
+            record["process"] = process_status_json(&process.status);
+
+            let output = String::from_utf8_lossy(&process.stdout).to_string();
+            if stream == JsonStream::Stderr {
+                print!("{}", output);
+            }
+
+            if !process.status.success() {
+                let error = HookError::NonZeroExit {
+                    code: process.status.code(),
+                    stderr: String::from_utf8_lossy(&process.stderr).trim_end().to_string(),
+                };
+                record["error"] = serde_json::json!(error.to_string());
+            } else if std::str::from_utf8(&process.stdout).is_err() {
+                let error = HookError::InvalidUtf8 { bytes: process.stdout.len() };
+                record["error"] = serde_json::json!(error.to_string());
+            } else {
+                match parse_hook_output(&output, strict_json) {
+                    Ok(parsed) => {
+                        let (decision, warning) = parsed.resolve_decision(&hook_opts.decision_map);
+                        record["continue"] = serde_json::json!(decision.allows_continue());
+                        if !decision.allows_continue() {
+                            exit_code = EXIT_BLOCKED;
+                        }
+                        record["decision"] = serde_json::json!(decision.as_str());
+                        record["systemMessage"] = serde_json::json!(parsed.system_message);
+                        if let Some(warning) = warning {
+                            record["warning"] = serde_json::json!(warning);
+                        }
+                    }
+                    Err(e) => record["error"] = serde_json::json!(HookError::InvalidOutput(e).to_string()),
+                }
+            }
+        }
+        Err(e) => {
+            record["error"] = serde_json::json!(e.to_string());
+            exit_code = e.exit_code();
+        }
+    }
+
+    if record.get("error").is_some() && exit_code == 0 {
+        exit_code = EXIT_HOOK_ERROR;
+    }
+
+    let record = masker.mask_json(&record);
+    match stream {
+        JsonStream::Stdout => println!("{}", record),
+        JsonStream::Stderr => eprintln!("{}", record),
+    }
+    exit_code
+}
+
+fn replay_transcript(path: &std::path::Path, stage: &str, hook_opts: &HookOptions, masker: &PathMasker) {
+    println!("{}", format!("=== Replaying Transcript ({}, stage: {}) ===", path.display(), stage).cyan().bold());
+
+    let events = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str::<Vec<serde_json::Value>>(&content).map_err(|e| e.to_string()))
+    {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", "Error:".red().bold(), path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut continued = 0;
+    let mut blocked = 0;
+    let mut warned = 0;
+    let mut errors = 0;
+    let mut unsupported = 0;
+    let mut invalid = 0;
+
+    println!();
+    for (index, raw) in events.into_iter().enumerate() {
+        let event = match serde_json::from_value::<TranscriptEvent>(raw) {
+            Ok(event) => event,
+            Err(e) => {
+                println!("  [{}] {} {}", index + 1, "Skipping invalid event:".red(), e);
+                invalid += 1;
+                continue;
+            }
+        };
+        if !SUPPORTED_TOOLS.contains(&event.tool_name.as_str()) {
+            println!("  [{}] {} {}", index + 1, event.tool_name, "skipped (unsupported)".dimmed());
+            unsupported += 1;
+            continue;
+        }
+
+        let hook_input = HookInput {
+            session_id: event.session_id.unwrap_or_else(|| format!("replay-{}", index + 1)),
+            tool_name: event.tool_name,
+            tool_input: event.tool_input,
+            cwd: event.cwd.unwrap_or_else(|| working_dir().to_string()),
+            version: None,
+        };
+        let input = serde_json::to_value(&hook_input).unwrap();
+        let target = hook_input.tool_input["file_path"].as_str().unwrap_or("");
+
+        let outcome = call_python_hook(stage, &input, hook_opts)
+            .and_then(|output| parse_hook_output(&output, false).map_err(HookError::InvalidOutput));
+        match outcome {
+            Ok(parsed) => {
+                let (decision, _) = parsed.resolve_decision(&hook_opts.decision_map);
+                match decision {
+                    Decision::Continue => continued += 1,
+                    Decision::Block => blocked += 1,
+                    Decision::Warn => warned += 1,
+                }
+                println!("  [{}] {} {}  {}", index + 1, hook_input.tool_name, masker.mask(target), decision.label());
+            }
+            Err(e) => {
+                errors += 1;
+                println!(
+                    "  [{}] {} {}  {} {}",
+                    index + 1,
+                    hook_input.tool_name,
+                    masker.mask(target),
+                    "💥 ERROR".red(),
+                    masker.mask(&e.to_string())
+                );
+            }
+        }
+    }
+
+    println!("\n{}", "Replay Summary:".green().bold());
+    println!("  Continue: {}", continued);
+    println!("  Blocked: {}", blocked);
+    if warned > 0 {
+        println!("  Warn: {}", warned);
+    }
+    if errors > 0 {
+        println!("  Errors: {}", errors.to_string().red());
+    }
+    if unsupported > 0 {
+        println!("  Skipped (unsupported tool): {}", unsupported);
+    }
+    if invalid > 0 {
+        println!("  Skipped (invalid): {}", invalid.to_string().red());
+    }
+}
+
+fn parse_hook_output(output: &str, strict: bool) -> Result<HookOutput, String> {
+    let result = hook_output_object(output).and_then(|value| {
+        if strict {
+            parse_strict_hook_output(value)
+        } else {
+            serde_json::from_value::<HookOutput>(value).map_err(|e| e.to_string())
+        }
+    });
+
+    match &result {
+        Ok(parsed) => tracing::debug!(strict, ?parsed, "parsed hook output"),
+        Err(e) => tracing::debug!(strict, error = %e, "hook output rejected"),
+    }
+    result
+}
+
+/// Parses the output as a generic JSON value first so "not JSON at all" and
+/// "JSON without the required fields" are reported differently
+fn hook_output_object(output: &str) -> Result<serde_json::Value, String> {
+    let value: serde_json::Value = serde_json::from_str(output).map_err(|e| format!("not JSON: {}", e))?;
+    let Some(obj) = value.as_object() else {
+        let kind = match value {
+            serde_json::Value::Null => "null",
+            serde_json::Value::Bool(_) => "a boolean",
+            serde_json::Value::Number(_) => "a number",
+            serde_json::Value::String(_) => "a string",
+            serde_json::Value::Array(_) => "an array",
+            serde_json::Value::Object(_) => unreachable!(),
+        };
+        return Err(format!("valid JSON, but {} instead of an object", kind));
+    };
+
+    if !obj.contains_key("continue") && !obj.contains_key("decision") {
+        let mut missing = vec!["`continue` (or `decision`)"];
+        if !obj.contains_key("systemMessage") {
+            missing.push("`systemMessage`");
+        }
+        let present: Vec<&str> = obj.keys().map(String::as_str).collect();
+        let present = if present.is_empty() { "none".to_string() } else { present.join(", ") };
+        return Err(format!("valid JSON, but missing {} (fields present: {})", missing.join(" and "), present));
+    }
+    Ok(value)
+}
+
+fn parse_strict_hook_output(value: serde_json::Value) -> Result<HookOutput, String> {
+    if let Some(obj) = value.as_object() {
+        let unexpected: Vec<&str> = obj
+            .keys()
+            .map(|k| k.as_str())
+            .filter(|k| !HOOK_OUTPUT_FIELDS.contains(k))
+            .collect();
+        if !unexpected.is_empty() {
+            return Err(format!("unexpected fields: {}", unexpected.join(", ")));
+        }
+    }
+
+    serde_json::from_value::<StrictHookOutput>(value)
+        .map(HookOutput::from)
+        .map_err(|e| e.to_string())
+}
+
+/// Exit code and terminating signal (Unix only) of a finished hook process
+fn process_status_json(status: &std::process::ExitStatus) -> serde_json::Value {
+    #[cfg(unix)]
+    let signal = {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    };
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+
+    serde_json::json!({
+        "exit_code": status.code(),
+        "signal": signal,
+    })
+}
+
+fn call_python_hook(stage: &str, input: &serde_json::Value, opts: &HookOptions) -> Result<String, HookError> {
+    call_python_hook_with_stderr(stage, input, opts).map(|(stdout, _)| stdout)
+}
+
+/// Like call_python_hook, but also returns what the hook wrote to stderr while
+/// still succeeding (deprecations, fallback notices, ...), trimmed
+fn call_python_hook_with_stderr(
+    stage: &str,
+    input: &serde_json::Value,
+    opts: &HookOptions,
+) -> Result<(String, String), HookError> {
+    let output = run_python_hook(stage, input, opts)?;
+
+    if output.status.success() {
+        let bytes = output.stdout.len();
+        let mut stdout = String::from_utf8(output.stdout).map_err(|_| HookError::InvalidUtf8 { bytes })?;
+        if opts.stream {
+            // Progress lines were already echoed; only the hook's JSON is parsed
+            stdout = streamed_json(&stdout).to_string();
+        }
+        warn_on_version_mismatch(&stdout, opts.protocol_version);
+        Ok((stdout, String::from_utf8_lossy(&output.stderr).trim_end().to_string()))
+    } else {
+        let error = HookError::NonZeroExit {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim_end().to_string(),
+        };
+        if opts.retries > 0 {
+            Err(HookError::Exhausted { attempts: opts.retries + 1, last: Box::new(error) })
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// The JSON document at the end of --stream output: the shortest suffix made of
+/// whole lines that parses, so pretty-printed output after progress lines still
+/// works. Falls back to the whole output so the parse error shows what was sent.
+fn streamed_json(stdout: &str) -> &str {
+    let line_starts = std::iter::once(0).chain(stdout.match_indices('\n').map(|(i, _)| i + 1));
+    let starts: Vec<usize> = line_starts.filter(|&start| start < stdout.len()).collect();
+    starts
+        .iter()
+        .rev()
+        .map(|&start| stdout[start..].trim())
+        .filter(|suffix| !suffix.is_empty())
+        .find(|suffix| serde_json::from_str::<serde_json::Value>(suffix).is_ok())
+        .unwrap_or(stdout)
+}
+
+/// Fails early when the hook script is missing instead of letting Python report it
+fn check_script(opts: &HookOptions) -> Result<(), HookError> {
+    if opts.script.is_file() { Ok(()) } else { Err(HookError::ScriptNotFound(opts.script.clone())) }
+}
+
+/// Warns (without failing) when the hook reports a protocol version other than the one sent
+fn warn_on_version_mismatch(stdout: &str, expected: u32) {
+    let reported = serde_json::from_str::<HookOutput>(stdout).ok().and_then(|output| output.version);
+    if let Some(version) = reported.filter(|v| *v != expected) {
+        eprintln!(
+            "{} hook speaks protocol version {}, but the CLI sent version {}",
+            "Warning:".yellow(),
+            version,
+            expected
+        );
+    }
+}
+
+/// Spawns the hook and returns the finished process, whatever its exit status.
+/// Spawn failures and non-zero exits are retried up to `opts.retries` times,
+/// waiting 200ms, 400ms, 800ms, ... between attempts; other errors return at once.
+fn run_python_hook(
+    stage: &str,
+    input: &serde_json::Value,
+    opts: &HookOptions,
+) -> Result<std::process::Output, HookError> {
+    // Also checked in spawn_hook, but here a missing script isn't recorded in the --db history
+    check_script(opts)?;
+    let mut attempt = 0;
+    loop {
+        let result = run_python_hook_once(stage, input, opts);
+        let failed = match &result {
+            Ok(output) => !output.status.success(),
+            Err(e) => e.is_transient(),
+        };
+        if !failed || attempt >= opts.retries {
+            return match result {
+                Err(e) if attempt > 0 => Err(HookError::Exhausted { attempts: attempt + 1, last: Box::new(e) }),
+                other => other,
+            };
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200 << attempt.min(16)));
+        attempt += 1;
+    }
+}
+
+/// The request the orchestrator reads from stdin, one per call or one per line with --serve.
+/// The only place the protocol version is set: on the wrapper and on the hook input alike,
+/// whichever command built the input.
+fn hook_wrapper(stage: &str, input: &serde_json::Value, opts: &HookOptions) -> serde_json::Value {
+    let mut input = input.clone();
+    if let Some(fields) = input.as_object_mut() {
+        fields.insert("version".to_string(), serde_json::json!(opts.protocol_version));
+    }
+    let mut wrapper = serde_json::json!({
+        "stage": stage,
+        "hook_input": input,
+        "version": opts.protocol_version
+    });
+    if !opts.adapters.is_empty() {
+        wrapper["adapters"] = serde_json::json!(opts.adapters);
+    }
+    if !opts.config_overrides.is_empty() {
+        let mut overrides = serde_json::json!({});
+        apply_config_overrides(&mut overrides, &opts.config_overrides);
+        wrapper["config_overrides"] = overrides;
+    }
+    wrapper
+}
+
+fn run_python_hook_once(
+    stage: &str,
+    input: &serde_json::Value,
+    opts: &HookOptions,
+) -> Result<std::process::Output, HookError> {
+    let wrapper_input = hook_wrapper(stage, input, opts);
+
+    let _span = tracing::debug_span!("hook_call", stage).entered();
+    let start = Instant::now();
+    let result = spawn_hook(&wrapper_input, opts);
+
+    if let Some(db_path) = &opts.db_path {
+        let record = invocation_record(stage, input, &result, start.elapsed(), opts);
+        if let Err(e) = db::record_invocation(db_path, &record) {
+            eprintln!("{} {}", "Warning:".yellow(), e);
+        }
+    }
+
+    result
+}
+
+/// Summarizes one hook run for the --db history; model and cost come from the
+/// hook output when the orchestrator reports them
+fn invocation_record(
+    stage: &str,
+    input: &serde_json::Value,
+    result: &Result<std::process::Output, HookError>,
+    elapsed: std::time::Duration,
+    opts: &HookOptions,
+) -> db::InvocationRecord {
+    let mut record = db::InvocationRecord {
+        stage: stage.to_string(),
+        input_hash: db::hash_input(input.get("tool_input").unwrap_or(input)),
+        decision: None,
+        exit_code: None,
+        latency_ms: elapsed.as_millis() as u64,
+        model: None,
+        cost: None,
+    };
+
+    if let Ok(output) = result {
+        record.exit_code = output.status.code();
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if let Ok(parsed) = parse_hook_output(&stdout, false) {
+                record.decision = Some(parsed.resolve_decision(&opts.decision_map).0.as_str().to_string());
+            }
+            if let Ok(raw) = serde_json::from_str::<serde_json::Value>(&stdout) {
+                record.model = raw.get("model").and_then(|m| m.as_str()).map(String::from);
+                record.cost = raw.get("cost").and_then(|c| c.as_f64());
+            }
+        }
+    }
+
+    record
+}
+
+/// A hook process shared with the Ctrl-C handler so it can be killed from there
+type SharedChild = std::sync::Arc<std::sync::Mutex<std::process::Child>>;
+
+/// Hook processes that haven't been reaped yet, for the Ctrl-C handler
+static RUNNING_HOOKS: std::sync::Mutex<Vec<(u64, SharedChild)>> = std::sync::Mutex::new(Vec::new());
+/// Set once Ctrl-C has been pressed
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+/// Set by commands that handle the first Ctrl-C themselves (Invoke --repeat)
+static DEFER_INTERRUPT: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Locks a mutex, ignoring poisoning: a panicked holder can't leave a Child half-updated
+fn lock_ignoring_poison<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keeps a hook process in RUNNING_HOOKS until dropped
+struct RunningHook(u64);
+
+impl RunningHook {
+    fn register(child: &SharedChild) -> Self {
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        lock_ignoring_poison(&RUNNING_HOOKS).push((id, child.clone()));
+        RunningHook(id)
+    }
+}
+
+impl Drop for RunningHook {
+    fn drop(&mut self) {
+        lock_ignoring_poison(&RUNNING_HOOKS).retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Kills and reaps every hook process still running, returning how many there were.
+/// One that already exited is left alone, so nothing is killed twice.
+fn kill_running_hooks(hooks: &[(u64, SharedChild)]) -> usize {
+    hooks
+        .iter()
+        .filter(|(_, child)| {
+            let mut child = lock_ignoring_poison(child);
+            if !matches!(child.try_wait(), Ok(None)) {
+                return false;
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            true
+        })
+        .count()
+}
+
+/// On Ctrl-C, kills any running hook process and exits with 130, so an
+/// interrupted --real, --repeat or watch run doesn't leave Python behind
+fn install_interrupt_handler() {
+    let installed = ctrlc::set_handler(|| {
+        let already = INTERRUPTED.swap(true, std::sync::atomic::Ordering::SeqCst);
+        // Held until exit: a hook call that sees its process die blocks on this
+        // lock while unregistering, instead of reporting the kill as a failure
+        let hooks = lock_ignoring_poison(&RUNNING_HOOKS);
+        let killed = kill_running_hooks(&hooks);
+        if DEFER_INTERRUPT.load(std::sync::atomic::Ordering::SeqCst) && !already {
+            return;
+        }
+        let message = if killed > 0 { "Interrupted; hook process terminated" } else { "Interrupted" };
+        eprintln!("\n{}", message.yellow());
+        std::process::exit(130);
+    });
+    if let Err(e) = installed {
+        tracing::warn!(error = %e, "could not install Ctrl-C handler");
+    }
+}
+
+/// A long-lived `<script> --serve` process that answers one JSON request per
+/// line with one JSON response per line (Bench --persistent)
+struct PersistentHook {
+    child: SharedChild,
+    _running: RunningHook,
+    stdin: std::process::ChildStdin,
+    /// Non-empty stdout lines, in order; disconnects when the process exits
+    responses: std::sync::mpsc::Receiver<String>,
+    stderr: Option<std::thread::JoinHandle<Vec<u8>>>,
+}
+
+impl PersistentHook {
+    fn spawn(opts: &HookOptions) -> Result<Self, HookError> {
+        check_script(opts)?;
+        let python = opts.python.get().as_ref().map_err(|e| HookError::PythonNotFound(e.clone()))?;
+        let mut child = hook_command(python, opts)
+            .arg("--serve")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|source| HookError::SpawnFailed { python: python.to_string(), source })?;
+        tracing::debug!(%python, script = %opts.script.display(), pid = child.id(), "spawned persistent hook");
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, responses) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(stdout).lines().map_while(Result::ok) {
+                if !line.trim().is_empty() && tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr = Some(drain_pipe(child.stderr.take()));
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let child = std::sync::Arc::new(std::sync::Mutex::new(child));
+        let _running = RunningHook::register(&child);
+        let mut hook = PersistentHook { child, _running, stdin, responses, stderr };
+
+        // --serve prints {"ready": true} once it's loaded, so interpreter startup
+        // stays out of the first request's timing
+        hook.receive(opts.timeout)?;
+        Ok(hook)
+    }
+
+    /// Sends one request and waits for its response line. After an error the
+    /// process is dead or out of step and must not be reused.
+    fn request(
+        &mut self,
+        wrapper: &serde_json::Value,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<String, HookError> {
+        if let Err(e) = writeln!(self.stdin, "{}", wrapper).and_then(|_| self.stdin.flush()) {
+            let exited = lock_ignoring_poison(&self.child).try_wait();
+            return Err(match exited {
+                Ok(Some(_)) => self.exited(),
+                _ => HookError::WriteFailed(e),
+            });
+        }
+        self.receive(timeout)
+    }
+
+    /// Waits for the next stdout line, killing the process if it takes longer than `timeout`
+    fn receive(&mut self, timeout: Option<std::time::Duration>) -> Result<String, HookError> {
+        let received = match timeout {
+            Some(timeout) => self.responses.recv_timeout(timeout),
+            None => self.responses.recv().map_err(|_| std::sync::mpsc::RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(line) => Ok(line),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                let timeout = timeout.expect("only a bounded wait can time out");
+                tracing::warn!(timeout_secs = timeout.as_secs(), "persistent hook timed out, killing it");
+                let mut child = lock_ignoring_poison(&self.child);
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(HookError::Timeout(timeout))
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Err(self.exited()),
+        }
+    }
+
+    /// Reaps a process that closed its stdout and reports how it went away
+    fn exited(&mut self) -> HookError {
+        // Waits out a Ctrl-C handler that killed this process and is about to exit
+        drop(lock_ignoring_poison(&RUNNING_HOOKS));
+        let code = {
+            let mut child = lock_ignoring_poison(&self.child);
+            let _ = child.kill();
+            child.wait().ok().and_then(|status| status.code())
+        };
+        let stderr = self.stderr.take().and_then(|reader| reader.join().ok()).unwrap_or_default();
+        HookError::NonZeroExit { code, stderr: String::from_utf8_lossy(&stderr).trim_end().to_string() }
+    }
+}
+
+impl Drop for PersistentHook {
+    fn drop(&mut self) {
+        let mut child = lock_ignoring_poison(&self.child);
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Waits for spawn_hook's stdin writer, returning when it finished writing
+fn join_writer(
+    writer: std::thread::JoinHandle<std::io::Result<std::time::Duration>>,
+) -> std::io::Result<std::time::Duration> {
+    writer.join().unwrap_or_else(|_| Err(std::io::Error::other("stdin writer panicked")))
+}
+
+/// Reads a child pipe to the end on a background thread
+fn drain_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Like drain_pipe, but echoes each line to stdout as soon as it is read
+fn stream_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(pipe) = pipe {
+            let mut reader = std::io::BufReader::new(pipe);
+            let mut line = Vec::new();
+            while let Ok(n) = std::io::BufRead::read_until(&mut reader, b'\n', &mut line) {
+                if n == 0 {
+                    break;
+                }
+                println!("{} {}", "│".dimmed(), String::from_utf8_lossy(&line).trim_end());
+                buf.append(&mut line);
+            }
+        }
+        buf
+    })
+}
+
+/// An interpreter plus any arguments it needs before the script, e.g. `py -3`
+#[derive(Clone, Debug)]
+struct PythonCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl PythonCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        PythonCommand { program: program.to_string(), args: args.iter().map(|a| a.to_string()).collect() }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
+
+impl std::fmt::Display for PythonCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// The --python interpreter, or the first PYTHON_CANDIDATES entry that runs.
+/// Probing waits until a hook is about to spawn, so commands that never run
+/// Python (generate, simulate, init, ...) don't pay for it; clones share the result.
+#[derive(Clone)]
+struct LazyPython {
+    explicit: Option<String>,
+    resolved: std::sync::Arc<std::sync::OnceLock<Result<PythonCommand, String>>>,
+}
+
+impl LazyPython {
+    fn new(explicit: Option<String>) -> Self {
+        LazyPython { explicit, resolved: Default::default() }
+    }
+
+    /// The interpreter, or why none could be found; probes on the first call only
+    fn get(&self) -> &Result<PythonCommand, String> {
+        self.resolved.get_or_init(|| resolve_python(self.explicit.as_deref()))
+    }
+}
+
+/// Interpreters to probe, in order: Windows has `python` (often the launcher)
+/// and `py`, while many Linux distros only ship `python3`
+const PYTHON_CANDIDATES: &[(&str, &[&str])] = if cfg!(windows) {
+    &[("python", &[]), ("py", &["-3"])]
+} else {
+    &[("python3", &[]), ("python", &[])]
+};
+
+/// Uses the explicit interpreter if given, otherwise the first candidate that
+/// runs. Called once at startup; HookOptions keeps the result.
+fn resolve_python(explicit: Option<&str>) -> Result<PythonCommand, String> {
+    if let Some(python) = explicit {
+        return Ok(PythonCommand::new(python, &[]));
+    }
+
+    let candidates: Vec<PythonCommand> =
+        PYTHON_CANDIDATES.iter().map(|(program, args)| PythonCommand::new(program, args)).collect();
+    for candidate in &candidates {
+        let works = candidate
+            .command()
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if works {
+            return Ok(candidate.clone());
+        }
+    }
+
+    let tried: Vec<String> = candidates.iter().map(|c| format!("`{}`", c)).collect();
+    Err(format!(
+        "No Python interpreter found (tried {}); pass --python <path> or set HOOK_TEST_PYTHON",
+        tried.join(", ")
+    ))
+}
+
+/// `<python> <script>` with the --env overrides applied, ready for stdio setup
+fn hook_command(python: &PythonCommand, opts: &HookOptions) -> Command {
+    let mut command = python.command();
+    command.arg(&opts.script).envs(opts.env.iter().map(|(k, v)| (k, v)));
+    command
+}
+
+/// hook_command as a shell line, e.g. `KEY=value python3 review_orchestrator.py`
+fn render_hook_command(python: &PythonCommand, opts: &HookOptions) -> String {
+    let quote = |s: &str| {
+        if s.is_empty() || s.chars().any(|c| c.is_whitespace() || "'\"$`\\".contains(c)) {
+            format!("'{}'", s.replace('\'', "'\\''"))
+        } else {
+            s.to_string()
+        }
+    };
+    let mut parts: Vec<String> = opts.env.iter().map(|(k, v)| format!("{}={}", k, quote(v))).collect();
+    parts.push(quote(&python.program));
+    parts.extend(python.args.iter().map(|a| quote(a)));
+    parts.push(quote(&opts.script.display().to_string()));
+    parts.join(" ")
+}
+
+/// Prints what a hook call for `stage` would run and send, without running it
+fn print_dry_run(
+    stage: &str,
+    input: &serde_json::Value,
+    opts: &HookOptions,
+    masker: &PathMasker,
+) -> Result<(), HookError> {
+    let python = opts.python.get().as_ref().map_err(|e| HookError::PythonNotFound(e.clone()))?;
+    println!("\n{}", format!("Dry run (stage: {}), nothing executed:", stage).yellow());
+    println!("  Command: {}", masker.mask(&render_hook_command(python, opts)));
+    println!("  Stdin:");
+    let wrapper = masker.mask_json(&hook_wrapper(stage, input, opts));
+    for line in serde_json::to_string_pretty(&wrapper).unwrap().lines() {
+        println!("    {}", line);
+    }
+    Ok(())
+}
+
+/// Sends one request to the orchestrator over stdin and waits for it to exit
+fn spawn_hook(wrapper_input: &serde_json::Value, opts: &HookOptions) -> Result<std::process::Output, HookError> {
+    spawn_hook_timed(wrapper_input, opts).map(|(output, _)| output)
+}
+
+/// Where one spawn_hook call spent its time
+struct HookPhases {
+    /// Starting the interpreter process
+    spawn: std::time::Duration,
+    /// Writing the wrapper JSON and closing stdin
+    write: std::time::Duration,
+    /// Waiting for the process to exit (startup, imports and the review itself)
+    wait: std::time::Duration,
+    /// Collecting stdout/stderr from the reader threads after exit
+    read: std::time::Duration,
+}
+
+/// spawn_hook, also reporting how long each phase took
+fn spawn_hook_timed(
+    wrapper_input: &serde_json::Value,
+    opts: &HookOptions,
+) -> Result<(std::process::Output, HookPhases), HookError> {
+    let verbose = opts.verbosity == Verbosity::Verbose;
+    if opts.show_wrapper || verbose {
+        eprintln!("{}", "Wrapper JSON:".dimmed());
+        eprintln!("{}", serde_json::to_string_pretty(wrapper_input).unwrap());
+    }
+
+    check_script(opts)?;
+    let python = opts.python.get().as_ref().map_err(|e| HookError::PythonNotFound(e.clone()))?;
+    if verbose {
+        eprintln!("{} {}", "Command:".dimmed(), render_hook_command(python, opts));
+    }
+
+    let spawn_start = Instant::now();
+    let mut child = hook_command(python, opts)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| HookError::SpawnFailed { python: python.to_string(), source })?;
+    let started = Instant::now();
+    let spawn = started - spawn_start;
+    tracing::debug!(%python, script = %opts.script.display(), pid = child.id(), "spawned hook");
+
+    // Drain both pipes on their own threads so a chatty hook can't fill a
+    // pipe buffer and deadlock while we poll for exit
+    let stdout_reader = if opts.stream {
+        stream_pipe(child.stdout.take())
+    } else {
+        drain_pipe(child.stdout.take())
+    };
+    let stderr_reader = drain_pipe(child.stderr.take());
+
+    // Write on its own thread, after registering the child and starting the
+    // clock: a hook that never reads stdin would otherwise block a large
+    // payload's write before the timeout (or Ctrl-C) could reach it
+    let stdin = child.stdin.take();
+    let child = std::sync::Arc::new(std::sync::Mutex::new(child));
+    let running = RunningHook::register(&child);
+    let payload = wrapper_input.to_string();
+    let mut writer = Some(std::thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            stdin.write_all(payload.as_bytes())?;
+            tracing::debug!(bytes = payload.len(), %payload, "wrote wrapper to stdin");
+        }
+        Ok(started.elapsed())
+    }));
+    let mut write = std::time::Duration::ZERO;
+
+    // Poll rather than block in wait() so the Ctrl-C handler can take the
+    // lock and kill the process; 1 ms keeps bench timings fine-grained
+    let status = loop {
+        let mut child = lock_ignoring_poison(&child);
+        if let Some(finished) = writer.take_if(|writer| writer.is_finished()) {
+            match join_writer(finished) {
+                Ok(elapsed) => write = elapsed,
+                Err(e) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(HookError::WriteFailed(e));
+                }
+            }
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {
+                if let Some(timeout) = opts.timeout.filter(|timeout| started.elapsed() >= *timeout) {
+                    tracing::warn!(timeout_secs = timeout.as_secs(), "hook timed out, killing it");
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(HookError::Timeout(timeout));
+                }
+            }
+            Err(e) => return Err(HookError::WaitFailed(e)),
+        }
+        drop(child);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    };
+    drop(running);
+    // The hook exited mid-write: its stdin is closed, so this returns promptly
+    if let Some(writer) = writer {
+        write = join_writer(writer).map_err(HookError::WriteFailed)?;
+    }
+
+    let exited = Instant::now();
+    let wait = (exited - started).saturating_sub(write);
+    let output = std::process::Output {
+        status,
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    };
+    let phases = HookPhases { spawn, write, wait, read: exited.elapsed() };
+    tracing::debug!(
+        exit_code = ?output.status.code(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        stdout = %String::from_utf8_lossy(&output.stdout),
+        stderr = %String::from_utf8_lossy(&output.stderr),
+        "hook exited"
+    );
+
+    if opts.show_response {
+        eprintln!("{}", "Raw hook stdout:".dimmed());
+        eprintln!("{}", String::from_utf8_lossy(&output.stdout).trim_end());
+    }
+    if verbose && !output.stderr.is_empty() {
+        eprintln!("{}", "Hook stderr:".dimmed());
+        eprintln!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+
+    Ok((output, phases))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_message(json: &str) -> Option<String> {
+        serde_json::from_str::<HookOutput>(json).unwrap().system_message
+    }
+
+    #[test]
+    fn system_message_accepts_a_string() {
+        assert_eq!(system_message(r#"{"continue": true, "systemMessage": "ok"}"#).as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn system_message_joins_an_array_with_newlines() {
+        let json = r#"{"continue": false, "systemMessage": ["line one", "line two"]}"#;
+        assert_eq!(system_message(json).as_deref(), Some("line one\nline two"));
+    }
+
+    #[test]
+    fn system_message_may_be_null_or_missing() {
+        assert_eq!(system_message(r#"{"continue": true, "systemMessage": null}"#), None);
+        assert_eq!(system_message(r#"{"continue": true}"#), None);
+    }
+
+    #[test]
+    fn system_message_rejects_other_shapes() {
+        assert!(serde_json::from_str::<HookOutput>(r#"{"systemMessage": 3}"#).is_err());
+        assert!(serde_json::from_str::<StrictHookOutput>(r#"{"systemMessage": ["a", 1]}"#).is_err());
+    }
+
+    #[test]
+    fn parse_hook_output_reports_missing_fields_for_valid_json() {
+        for strict in [false, true] {
+            let err = parse_hook_output(r#"{"foo": 1}"#, strict).unwrap_err();
+            let expected = "valid JSON, but missing `continue` (or `decision`) and `systemMessage`";
+            assert!(err.starts_with(expected), "{}", err);
+            assert!(err.ends_with("(fields present: foo)"), "{}", err);
+        }
+        let err = parse_hook_output(r#"{"systemMessage": "hi"}"#, false).unwrap_err();
+        assert_eq!(err, "valid JSON, but missing `continue` (or `decision`) (fields present: systemMessage)");
+    }
+
+    #[test]
+    fn parse_hook_output_reports_non_json_as_a_parse_error() {
+        for strict in [false, true] {
+            let err = parse_hook_output("Traceback (most recent call last):", strict).unwrap_err();
+            assert!(err.starts_with("not JSON: "), "{}", err);
+        }
+        let err = parse_hook_output("[1, 2]", false).unwrap_err();
+        assert_eq!(err, "valid JSON, but an array instead of an object");
+    }
+
+    #[test]
+    fn parse_hook_output_accepts_decision_without_continue() {
+        let parsed = parse_hook_output(r#"{"decision": "block", "systemMessage": "no"}"#, true).unwrap();
+        assert_eq!(parsed.decision.as_deref(), Some("block"));
+    }
+
+    /// HookOptions that run `script` with whatever Python the machine has
+    fn fake_hook_options(script: std::path::PathBuf) -> HookOptions {
+        HookOptions {
+            show_wrapper: false,
+            show_response: false,
+            decision_map: HashMap::new(),
+            db_path: None,
+            python: LazyPython::new(None),
+            script,
+            timeout: Some(std::time::Duration::from_secs(10)),
+            retries: 0,
+            env: Vec::new(),
+            verbosity: Verbosity::Quiet,
+            stream: false,
+            protocol_version: PROTOCOL_VERSION,
+            adapters: Vec::new(),
+            config_overrides: Vec::new(),
+            config_dir: std::path::PathBuf::new(),
+            stages: STAGES.iter().map(|stage| stage.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn non_utf8_hook_output_is_an_error() {
+        let script = std::env::temp_dir().join(format!("hook_test_invalid_utf8_{}.py", std::process::id()));
+        std::fs::write(
+            &script,
+            r#"import sys
+sys.stdin.read()
+sys.stdout.buffer.write(b'{"continue": true, "systemMessage": "\xff\xfe"}\n')
+"#,
+        )
+        .unwrap();
+        let opts = fake_hook_options(script.clone());
+        if let Err(e) = opts.python.get() {
+            eprintln!("skipping: {}", e);
+            return;
+        }
+
+        let result = call_python_hook("code", &serde_json::json!({}), &opts);
+        std::fs::remove_file(&script).unwrap();
+        match result {
+            Err(HookError::InvalidUtf8 { bytes }) => assert_eq!(bytes, 42),
+            other => panic!("expected InvalidUtf8, got {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+
+    fn strip(language: &str, code: &str) -> String {
+        comment_syntax(language).unwrap().strip(code)
+    }
+
+    #[test]
+    fn comment_syntax_covers_rust_python_javascript_and_go() {
+        for language in ["rust", "python", "javascript", "typescript", "go", "Rust"] {
+            assert!(comment_syntax(language).is_some(), "{}", language);
+        }
+        assert!(comment_syntax("sql").is_none());
+        assert!(comment_syntax("").is_none());
+    }
+
+    #[test]
+    fn strip_keeps_line_numbers() {
+        let code = "fn a() {}\n/* one\ntwo */\n// three\nfn b() {} // four\n";
+        assert_eq!(strip("rust", code), "fn a() {}\n\n\n\nfn b() {}\n");
+        assert_eq!(strip("rust", code).lines().count(), code.lines().count());
+    }
+
+    #[test]
+    fn strip_rust_ignores_markers_inside_strings() {
+        assert_eq!(strip("rust", r#"let url = "http://x"; // y"#), r#"let url = "http://x";"#);
+        assert_eq!(strip("rust", r#"let s = "/* not */"; /* yes */"#), r#"let s = "/* not */";"#);
+        assert_eq!(strip("rust", r#"let s = "a\"// b"; // c"#), r#"let s = "a\"// b";"#);
+        assert_eq!(strip("rust", r#"let s = "a\\"; // c"#), r#"let s = "a\\";"#);
+        assert_eq!(strip("rust", r#"let b = b"//"; // c"#), r#"let b = b"//";"#);
+    }
+
+    #[test]
+    fn strip_rust_raw_strings() {
+        assert_eq!(strip("rust", r###"let s = r#"say "//" here"#; // c"###), r###"let s = r#"say "//" here"#;"###);
+        assert_eq!(strip("rust", r#"let s = r"C:\"; // c"#), r#"let s = r"C:\";"#);
+        assert_eq!(strip("rust", r##"let s = br#"/*"#; /* c */"##), r##"let s = br#"/*"#;"##);
+        // `for"` isn't a raw string, the `r` belongs to the keyword
+        assert_eq!(strip("rust", r#"for"x" // c"#), r#"for"x""#);
+    }
+
+    #[test]
+    fn strip_rust_tells_chars_from_lifetimes() {
+        assert_eq!(strip("rust", r#"let q = '"'; // c"#), r#"let q = '"';"#);
+        assert_eq!(strip("rust", r"let q = '\''; // c"), r"let q = '\'';");
+        assert_eq!(strip("rust", r"let e = '\u{1F600}'; // c"), r"let e = '\u{1F600}';");
+        assert_eq!(strip("rust", "fn f<'a>(x: &'a str) {} // c"), "fn f<'a>(x: &'a str) {}");
+        assert_eq!(strip("rust", "'outer: loop { break 'outer; } // c"), "'outer: loop { break 'outer; }");
+    }
+
+    #[test]
+    fn strip_rust_block_comments_nest() {
+        assert_eq!(strip("rust", "a /* x /* y */ still comment */ b"), "a   b");
+        assert_eq!(strip("rust", "a/**/b"), "a b");
+        assert_eq!(strip("rust", "a /* unterminated\nb"), "a\n");
+    }
+
+    #[test]
+    fn strip_rust_doc_comments() {
+        assert_eq!(strip("rust", "/// Docs\n//! Inner\nfn f() {}"), "\n\nfn f() {}");
+        assert_eq!(strip("rust", "/** Block docs */\nfn f() {}"), "\nfn f() {}");
+    }
+
+    #[test]
+    fn strip_python_comments_and_strings() {
+        assert_eq!(strip("python", "x = 1  # comment"), "x = 1");
+        assert_eq!(strip("python", "s = '#not' # yes"), "s = '#not'");
+        assert_eq!(strip("python", r#"s = "it's # here" # yes"#), r#"s = "it's # here""#);
+        assert_eq!(strip("python", r#"s = 'a\'#b' # c"#), r#"s = 'a\'#b'"#);
+        assert_eq!(strip("python", "s = ''  # empty"), "s = ''");
+        // Python has no block comments, and `//` is floor division
+        assert_eq!(strip("python", "x = a // b  # floor"), "x = a // b");
+    }
+
+    #[test]
+    fn strip_python_triple_quoted_strings() {
+        let code = "def f():\n    \"\"\"Doc with # and \" and '''\n    # still doc\n    \"\"\"\n    return 1  # c\n";
+        let expected = "def f():\n    \"\"\"Doc with # and \" and '''\n    # still doc\n    \"\"\"\n    return 1\n";
+        assert_eq!(strip("python", code), expected);
+        assert_eq!(strip("python", "s = '''a\n# b\n''' # c"), "s = '''a\n# b\n'''");
+    }
+
+    #[test]
+    fn strip_javascript_strings_and_templates() {
+        assert_eq!(strip("javascript", r#"const u = "http://x"; // c"#), r#"const u = "http://x";"#);
+        assert_eq!(strip("javascript", "const u = 'a//b'; /* c */"), "const u = 'a//b';");
+        assert_eq!(strip("javascript", "const t = `line\n// kept\n`; // c"), "const t = `line\n// kept\n`;");
+    }
+
+    #[test]
+    fn strip_javascript_block_comments_do_not_nest() {
+        assert_eq!(strip("javascript", "a /* x /* y */ b */"), "a   b */");
+    }
+
+    #[test]
+    fn strip_javascript_regex_literals() {
+        assert_eq!(strip("javascript", r#"s.replace(/"/g, ""); // c"#), r#"s.replace(/"/g, "");"#);
+        assert_eq!(strip("javascript", r"const re = /\/\//; // c"), r"const re = /\/\//;");
+        assert_eq!(strip("javascript", r"const re = /[/]/; // c"), r"const re = /[/]/;");
+        assert_eq!(strip("javascript", r"return /'/.test(s); // c"), r"return /'/.test(s);");
+    }
+
+    #[test]
+    fn strip_javascript_division_is_not_a_regex() {
+        assert_eq!(strip("javascript", "const x = a / b / c; // c"), "const x = a / b / c;");
+        assert_eq!(strip("javascript", "const x = (a) / 2; // c'"), "const x = (a) / 2;");
+        assert_eq!(strip("typescript", "let r = n / d; /* c */"), "let r = n / d;");
+    }
+
+    #[test]
+    fn strip_go_strings_runes_and_raw_strings() {
+        assert_eq!(strip("go", r#"u := "http://x" // c"#), r#"u := "http://x""#);
+        assert_eq!(strip("go", r"p := `C:\` // c"), r"p := `C:\`");
+        assert_eq!(strip("go", "s := `a\n// kept\n` // c"), "s := `a\n// kept\n`");
+        assert_eq!(strip("go", r#"q := '"' // c"#), r#"q := '"'"#);
+        assert_eq!(strip("go", "a /* x /* y */ b"), "a   b");
+    }
+
+    #[test]
+    fn strip_leaves_non_ascii_text_alone() {
+        assert_eq!(strip("python", "msg = \"검토 # 통과\"  # 주석"), "msg = \"검토 # 통과\"");
+        assert_eq!(strip("rust", "let s = \"é\"; /* ü */ let t = 1;"), "let s = \"é\";   let t = 1;");
+    }
+
+    #[test]
+    fn strip_input_comments_uses_the_input_language() {
+        let mut input = serde_json::json!({
+            "tool_input": {"file_path": "a.py", "old_string": "x = 1  # old", "new_string": "x = 2  # new"}
+        });
+        set_language(&mut input, None);
+        strip_input_comments(&mut input).unwrap();
+        assert_eq!(input["tool_input"]["old_string"], "x = 1");
+        assert_eq!(input["tool_input"]["new_string"], "x = 2");
+
+        let mut unknown = serde_json::json!({"tool_input": {"file_path": "q.sql", "content": "-- c"}});
+        set_language(&mut unknown, None);
+        let err = strip_input_comments(&mut unknown).unwrap_err();
+        assert!(err.contains("'sql'"), "{}", err);
+    }
+
+    #[test]
+    fn describe_language_lists_comment_markers() {
+        let input = |language: &str| serde_json::json!({"tool_input": {"language": language}});
+        assert_eq!(describe_language(&input("rust")), "rust (comments: //, /* */ (nesting))");
+        assert_eq!(describe_language(&input("python")), "python (comments: #)");
+        assert_eq!(describe_language(&input("sql")), "sql (comment syntax unknown)");
+        assert_eq!(describe_language(&serde_json::json!({"tool_input": {}})), "none detected");
+    }
+
+    #[test]
+    fn parse_batch_skips_or_aborts_on_malformed_entries() {
+        let content = "{\"tool\": \"Edit\", \"file\": \"a.rs\"}\n\n{\"tool\": \"Write\"\n{\"file\": \"b.rs\"}\n";
+
+        let Ok((entries, skipped)) = parse_batch(content, false) else { panic!("lenient parse failed") };
+        let parsed: Vec<(usize, &str)> = entries.iter().map(|(line, entry)| (*line, entry.tool.as_str())).collect();
+        assert_eq!(parsed, [(1, "Edit")]);
+        assert_eq!(skipped, 2);
+
+        let Err(e) = parse_batch(content, true) else { panic!("strict parse accepted a malformed entry") };
+        assert!(e.starts_with("malformed entry 2 (line 3): EOF while parsing an object"), "{}", e);
+    }
+
+    #[test]
+    fn glob_match_keeps_single_star_within_a_segment() {
+        assert!(glob_match("*.swp", ".main.rs.swp"));
+        assert!(glob_match("src/*.rs", "src/main.rs"));
+        assert!(!glob_match("src/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("**/target", "a/b/target"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+    }
+
+    #[test]
+    fn is_ignored_matches_names_anywhere_and_paths_from_the_root() {
+        let ignore = vec!["*.swp".to_string(), "target/".to_string(), "docs/generated".to_string()];
+        let ignored = |path: &str| is_ignored(std::path::Path::new(path), &ignore);
+
+        assert!(ignored("src/.main.rs.swp"));
+        assert!(ignored("target/debug/app"));
+        assert!(ignored("crates/core/target/debug/app"));
+        assert!(ignored("docs/generated/index.html"));
+        assert!(ignored(".git/HEAD"));
+        assert!(!ignored("src/main.rs"));
+        assert!(!ignored("docs/guide.md"));
+        assert!(!ignored("src/docs/generated/index.html"));
+    }
+
+    #[test]
+    fn hook_wrapper_sets_the_protocol_version_on_every_input() {
+        let opts = HookOptions { protocol_version: 7, ..fake_hook_options(std::path::PathBuf::new()) };
+        let built = HookInput {
+            session_id: "s".to_string(),
+            tool_name: "Edit".to_string(),
+            tool_input: serde_json::json!({}),
+            cwd: ".".to_string(),
+            version: None,
+        };
+        let inputs = [
+            serde_json::to_value(&built).unwrap(),
+            serde_json::json!({"session_id": "s", "tool_name": "Edit", "tool_input": {}, "cwd": "."}),
+            serde_json::json!({"session_id": "s", "tool_name": "Edit", "tool_input": {}, "cwd": ".", "version": 1}),
+        ];
+        for input in &inputs {
+            let wrapper = hook_wrapper("code", input, &opts);
+            assert_eq!(wrapper["version"], 7);
+            assert_eq!(wrapper["hook_input"]["version"], 7);
+        }
+    }
+
+    #[test]
+    fn streamed_json_finds_multi_line_json_after_progress_lines() {
+        let stdout = "Reviewing with gemini...\n{\"progress\": 1}\n{\n  \"continue\": false,\n  \"systemMessage\": \"a\\nb\"\n}\n\n";
+        let json = streamed_json(stdout);
+        assert!(json.starts_with('{') && json.ends_with('}'), "{}", json);
+        let parsed = parse_hook_output(json, false).expect("streamed JSON should parse");
+        assert_eq!(parsed.should_continue, Some(false));
+        assert_eq!(parsed.system_message.as_deref(), Some("a\nb"));
+
+        assert_eq!(streamed_json("progress\n{\"continue\": true}"), "{\"continue\": true}");
+        assert_eq!(streamed_json("progress only\n"), "progress only\n");
+    }
+
+    #[test]
+    fn parse_served_response_fails_on_error_and_unparseable_responses() {
+        let parsed = parse_served_response(r#"{"continue": false, "systemMessage": "no"}"#).unwrap();
+        assert_eq!(parsed.should_continue, Some(false));
+
+        let Err(HookError::Reported(e)) = parse_served_response(r#"{"error": "config.json is invalid"}"#) else {
+            panic!("an error response should fail the iteration")
+        };
+        assert_eq!(e, "config.json is invalid");
+
+        assert!(matches!(parse_served_response("Traceback"), Err(HookError::InvalidOutput(_))));
+        assert!(matches!(parse_served_response(r#"{"foo": 1}"#), Err(HookError::InvalidOutput(_))));
+    }
+
+    #[test]
+    fn vary_test_content_renames_functions_reproducibly() {
+        let base = test_file_content("bug", Lang::Rust);
+        let first = vary_test_content(base, 1, 2, &mut SplitMix64(7));
+        assert_eq!(first, vary_test_content(base, 1, 2, &mut SplitMix64(7)));
+        assert!(first.starts_with("// Test file with intentional bug (variant 1 of 2)\n"), "{}", first);
+        assert!(!first.contains("calculate_average("), "{}", first);
+        assert!(first.contains("fn main()"), "{}", first);
+
+        let renamed = defined_functions(&first)[0];
+        assert!(renamed.starts_with("calculate_average_"), "{}", renamed);
+        assert_eq!(first.matches(renamed).count(), 2, "definition and call site should both be renamed");
+
+        let js = vary_test_content(test_file_content("bug", Lang::Javascript), 1, 2, &mut SplitMix64(7));
+        let renamed = defined_functions(&js)[0];
+        assert!(renamed.starts_with("calculateAverage") && renamed.len() > "calculateAverage".len(), "{}", renamed);
+    }
+
+    #[test]
+    fn replace_identifier_only_replaces_whole_words() {
+        let code = "fn total() {}\nfn total_len() { total(); subtotal() }";
+        assert_eq!(replace_identifier(code, "total", "sum"), "fn sum() {}\nfn total_len() { sum(); subtotal() }");
     }
 }
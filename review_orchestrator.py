@@ -18,7 +18,7 @@ import sys
 import json
 import os
 from pathlib import Path
-from typing import Dict, Any, List
+from typing import Dict, Any, List, Optional
 from concurrent.futures import ThreadPoolExecutor, as_completed
 from datetime import datetime
 
@@ -47,12 +47,54 @@ class AuditLogger:
         with open(log_file, "a") as f:
             f.write(json.dumps(event, ensure_ascii=False) + "\n")
 
+    def last_review(self, session_id: str, stage: str) -> Optional[Dict[str, Any]]:
+        """해당 세션/단계의 가장 최근 review 이벤트 (최신 로그 파일부터 검색)"""
+        for log_file in sorted(self.log_dir.glob("audit-*.jsonl"), reverse=True):
+            last = None
+            with open(log_file, encoding="utf-8") as f:
+                for line in f:
+                    try:
+                        event = json.loads(line)
+                    except json.JSONDecodeError:
+                        continue
+                    if (event.get("event_type") == "review" and event.get("session_id") == session_id
+                            and event.get("stage") == stage):
+                        last = event
+            if last:
+                return last
+        return None
+
+
+def merge_config(base: Dict[str, Any], overrides: Dict[str, Any]) -> None:
+    """overrides를 base에 재귀적으로 병합 (dict는 병합, 나머지는 교체)"""
+    for key, value in overrides.items():
+        if isinstance(value, dict) and isinstance(base.get(key), dict):
+            merge_config(base[key], value)
+        else:
+            base[key] = value
+
+
+# config.json에 review_stages가 없거나 잘못되었을 때의 검토 단계 (hook-test의 STAGES와 동일)
+DEFAULT_REVIEW_STAGES = ["plan", "code", "test", "final"]
+
+
+def review_stages(config: Dict[str, Any]) -> List[str]:
+    """config의 review_stages (비어 있지 않은 단계 이름 배열이 아니면 기본 단계)"""
+    stages = config.get("review_stages")
+    if isinstance(stages, list) and stages and all(isinstance(s, str) and s.strip() for s in stages):
+        return stages
+    return list(DEFAULT_REVIEW_STAGES)
+
 
 class ReviewOrchestrator:
     """다중 LLM 리뷰 오케스트레이터"""
 
-    def __init__(self):
+    def __init__(self, adapters: Optional[List[str]] = None,
+                 config_overrides: Optional[Dict[str, Any]] = None):
         self.config = load_config()
+        # hook-test --set 으로 전달된 값을 설정 위에 덮어씀
+        if config_overrides:
+            merge_config(self.config, config_overrides)
         self.state_manager = get_state_manager()
         self.security = get_security_validator()
         self.audit_logger = AuditLogger()
@@ -60,6 +102,9 @@ class ReviewOrchestrator:
         # 어댑터 초기화
         self.adapters = []
         enabled = self.config.get("enabled_adapters", ["gemini", "copilot"])
+        # 요청에 adapters가 지정되면 해당 어댑터만 사용 (hook-test invoke --adapter)
+        if adapters:
+            enabled = adapters
 
         if "gemini" in enabled:
             adapter = GeminiAdapter(self.config)
@@ -255,6 +300,37 @@ class ReviewOrchestrator:
 
         return "\n".join(messages)
 
+    def explain(self, stage: str, hook_input: Dict[str, Any]) -> List[str]:
+        """판정 근거 설명 (hook-test invoke --explain) - 리뷰를 다시 실행하지 않고 감사 로그와 설정만 읽음"""
+        session_id = hook_input.get("session_id", "unknown")
+        lines = []
+
+        last = self.audit_logger.last_review(session_id, stage)
+        if last:
+            decision = "continue" if last.get("continue_decision", True) else "block"
+            lines.append(f"마지막 리뷰 ({last.get('timestamp', '?')}): 최종 심각도 {last.get('final_severity')} → {decision}")
+            for result in last.get("llm_results", []):
+                if not result.get("success", True):
+                    lines.append(f"- {result.get('adapter')}: 실패 ({result.get('error')}), 판정에서 제외")
+                    continue
+                lines.append(f"- {result.get('adapter')}: {result.get('severity')}, 이슈 {len(result.get('issues', []))}개")
+        else:
+            lines.append(f"세션 {session_id}의 {stage} 단계 리뷰 기록이 감사 로그에 없습니다")
+
+        policy = self.config.get("conflict_resolution", {}).get("policy", "conservative")
+        lines.append(f"충돌 해결 정책: {policy}")
+
+        rework_config = self.config.get("rework_settings", {})
+        stage_config = self.config.get("stage_settings", {}).get(stage, {})
+        max_retries = stage_config.get("max_retries", rework_config.get("max_retries", 3))
+        retry_count = self.state_manager.get_retry_count(session_id, stage)
+        lines.append(f"CRITICAL/HIGH면 block, 재시도 {max_retries}회 소진 후에는 경고와 함께 continue "
+                     f"(현재 {retry_count}/{max_retries})")
+
+        if self.check_debounce(session_id, stage):
+            lines.append("debounce 중: 지금 호출하면 리뷰 없이 continue")
+        return lines
+
     def orchestrate(self, stage: str, hook_input: Dict[str, Any]) -> Dict[str, Any]:
         """메인 오케스트레이션 로직"""
         context = self.extract_context(hook_input)
@@ -323,6 +399,53 @@ class ReviewOrchestrator:
         }
 
 
+def to_hook_output(result: Dict[str, Any]) -> Dict[str, Any]:
+    """오케스트레이터 결과를 Claude Code Hook 형식으로 변환"""
+    output = {}
+    if result.get("systemMessage"):
+        output["systemMessage"] = result["systemMessage"]
+
+    if result.get("decision") == "block":
+        output["continue"] = False
+    else:
+        output["continue"] = True
+
+    return output
+
+
+def serve():
+    """상주 모드 (hook-test bench --persistent): 한 줄에 요청 하나, 한 줄에 응답 하나"""
+    # 기본 오케스트레이터를 미리 만들고 준비 완료를 알림 (첫 요청에 기동 비용이 섞이지 않도록)
+    orchestrators: Dict[Any, ReviewOrchestrator] = {None: ReviewOrchestrator()}
+    sys.stdout.write(json.dumps({"ready": True}) + "\n")
+    sys.stdout.flush()
+
+    for line in sys.stdin:
+        if not line.strip():
+            continue
+        try:
+            input_data = json.loads(line)
+            adapters = input_data.get("adapters")
+            overrides = input_data.get("config_overrides")
+            key = (tuple(adapters) if adapters else None,
+                   json.dumps(overrides, sort_keys=True) if overrides else None)
+            if key == (None, None):
+                key = None
+            if key not in orchestrators:
+                orchestrators[key] = ReviewOrchestrator(adapters=adapters, config_overrides=overrides)
+            result = orchestrators[key].orchestrate(
+                input_data.get("stage", "code"),
+                input_data.get("hook_input", input_data)
+            )
+            output = to_hook_output(result)
+        except Exception as e:
+            # 검토 결과로 위장하지 않고 실패를 알림 (CLI가 실패한 반복으로 집계)
+            output = {"error": f"{type(e).__name__}: {e}"}
+
+        sys.stdout.write(json.dumps(output, ensure_ascii=False) + "\n")
+        sys.stdout.flush()
+
+
 def main():
     """CLI 엔트리포인트"""
     # Windows에서 UTF-8 출력 설정
@@ -330,6 +453,10 @@ def main():
     sys.stdout = io.TextIOWrapper(sys.stdout.buffer, encoding='utf-8')
     sys.stdin = io.TextIOWrapper(sys.stdin.buffer, encoding='utf-8')
 
+    if "--serve" in sys.argv[1:]:
+        serve()
+        return
+
     # stdin에서 입력 읽기
     try:
         input_data = json.load(sys.stdin)
@@ -340,23 +467,47 @@ def main():
         }))
         sys.exit(0)
 
+    # 기능 조회 요청 (hook-test status)
+    if input_data.get("command") == "capabilities":
+        config = load_config()
+        if input_data.get("config_overrides"):
+            merge_config(config, input_data["config_overrides"])
+        print(json.dumps({
+            "events": ["PostToolUse", "Stop"],
+            "stages": review_stages(config)
+        }))
+        sys.exit(0)
+
+    # 어댑터 목록 조회 요청 (hook-test list-adapters)
+    if input_data.get("command") == "list_adapters":
+        config = load_config()
+        adapters = []
+        for name, adapter_class in (("gemini", GeminiAdapter), ("copilot", CopilotAdapter)):
+            try:
+                available = adapter_class(config).is_available()
+            except Exception:
+                available = False
+            adapters.append({"name": name, "available": available})
+        print(json.dumps({"adapters": adapters}))
+        sys.exit(0)
+
     stage = input_data.get("stage", "code")
     hook_input = input_data.get("hook_input", input_data)
 
-    orchestrator = ReviewOrchestrator()
-    result = orchestrator.orchestrate(stage, hook_input)
+    orchestrator = ReviewOrchestrator(
+        adapters=input_data.get("adapters"),
+        config_overrides=input_data.get("config_overrides")
+    )
 
-    # Claude Code Hook 형식으로 출력
-    output = {}
-    if result.get("systemMessage"):
-        output["systemMessage"] = result["systemMessage"]
+    # 판정 근거 조회 요청 (hook-test invoke --explain) - 리뷰를 다시 실행하지 않음
+    if input_data.get("command") == "explain":
+        print(json.dumps({"explanation": orchestrator.explain(stage, hook_input)}, ensure_ascii=False))
+        sys.exit(0)
 
-    if result.get("decision") == "block":
-        output["continue"] = False
-    else:
-        output["continue"] = True
+    result = orchestrator.orchestrate(stage, hook_input)
 
-    print(json.dumps(output, ensure_ascii=False))
+    # Claude Code Hook 형식으로 출력
+    print(json.dumps(to_hook_output(result), ensure_ascii=False))
 
 
 if __name__ == "__main__":